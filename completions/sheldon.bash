@@ -27,9 +27,15 @@ _sheldon() {
             sheldon,lock)
                 cmd="sheldon__lock"
                 ;;
+            sheldon,outdated)
+                cmd="sheldon__outdated"
+                ;;
             sheldon,remove)
                 cmd="sheldon__remove"
                 ;;
+            sheldon,rollback)
+                cmd="sheldon__rollback"
+                ;;
             sheldon,source)
                 cmd="sheldon__source"
                 ;;
@@ -43,7 +49,7 @@ _sheldon() {
 
     case "${cmd}" in
         sheldon)
-            opts="-q -v -h -V --quiet --verbose --color --config-dir --data-dir --config-file --profile --help --version init add edit remove lock source completions version"
+            opts="-q -v -h -V --quiet --verbose --color --config-dir --data-dir --config-file --profile --offline --help --version init add edit remove lock source outdated rollback completions version"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 1 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -193,7 +199,29 @@ _sheldon() {
             return 0
             ;;
         sheldon__lock)
-            opts="-h --update --reinstall --help"
+            opts="-h --update --log --reinstall --frozen --help"
+            if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
+                COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+                return 0
+            fi
+            case "${prev}" in
+                --update)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --log)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                *)
+                    COMPREPLY=()
+                    ;;
+            esac
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
+        sheldon__outdated)
+            opts="-h --help"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -220,6 +248,24 @@ _sheldon() {
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
+        sheldon__rollback)
+            opts="-h --to --help"
+            if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
+                COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+                return 0
+            fi
+            case "${prev}" in
+                --to)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                *)
+                    COMPREPLY=()
+                    ;;
+            esac
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
         sheldon__source)
             opts="-h --relock --update --reinstall --help"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
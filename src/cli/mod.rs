@@ -16,7 +16,7 @@ use clap::{CommandFactory, Parser};
 use clap_complete as complete;
 
 use crate::cli::raw::{Add, RawCommand, RawOpt};
-use crate::config::{EditPlugin, GitReference, RawPlugin, Shell};
+use crate::config::{EditPlugin, GitReference, NetworkOptions, RawPlugin, Shell};
 use crate::context::{log_error, log_error_as_warning, Context, Output, Verbosity};
 use crate::lock::LockMode;
 use crate::util::build;
@@ -56,6 +56,10 @@ pub enum Command {
     Lock,
     /// Generate and print out the script.
     Source,
+    /// Show plugins that are behind upstream.
+    Outdated,
+    /// Restore a previous lock file and check out its plugin sources.
+    Rollback { to: usize },
 }
 
 impl Opt {
@@ -68,10 +72,13 @@ impl Opt {
             config_dir,
             config_file,
             profile,
+            offline,
             command,
         } = raw_opt;
 
         let mut lock_mode = None;
+        let mut update_plugins = Vec::new();
+        let mut update_log = None;
 
         let command = match command {
             RawCommand::Init { shell } => Command::Init { shell },
@@ -84,8 +91,15 @@ impl Opt {
             }
             RawCommand::Edit => Command::Edit,
             RawCommand::Remove { name } => Command::Remove { name },
-            RawCommand::Lock { update, reinstall } => {
-                lock_mode = LockMode::from_lock_flags(update, reinstall);
+            RawCommand::Lock {
+                update,
+                log,
+                reinstall,
+                frozen,
+            } => {
+                lock_mode = LockMode::from_lock_flags(update.is_some(), reinstall, frozen);
+                update_plugins = update.unwrap_or_default();
+                update_log = log;
                 Command::Lock
             }
             RawCommand::Source {
@@ -96,6 +110,11 @@ impl Opt {
                 lock_mode = LockMode::from_source_flags(relock, update, reinstall);
                 Command::Source
             }
+            RawCommand::Outdated => Command::Outdated,
+            RawCommand::Rollback { to } => {
+                lock_mode = Some(LockMode::Normal);
+                Command::Rollback { to }
+            }
             RawCommand::Completions { shell } => {
                 let shell = complete::Shell::from(shell);
                 let mut app = RawOpt::command();
@@ -157,6 +176,10 @@ impl Opt {
             profile,
             output,
             lock_mode,
+            update_plugins,
+            update_log,
+            offline,
+            network: NetworkOptions::default(),
         };
 
         Self { ctx, command }
@@ -199,11 +222,21 @@ impl EditPlugin {
                 gist,
                 github,
                 remote,
+                archive: None,
+                github_release: None,
+                asset: None,
                 local,
                 inline: None,
+                command: None,
                 proto,
                 reference,
+                sha256: None,
+                strip_components: None,
+                depth: None,
+                filter: None,
                 dir,
+                sparse: None,
+                submodules: None,
                 uses,
                 apply,
                 profiles,
@@ -214,12 +247,13 @@ impl EditPlugin {
 }
 
 impl LockMode {
-    fn from_lock_flags(update: bool, reinstall: bool) -> Option<Self> {
-        match (update, reinstall) {
-            (false, false) => Some(Self::Normal),
-            (true, false) => Some(Self::Update),
-            (false, true) => Some(Self::Reinstall),
-            (true, true) => unreachable!(),
+    fn from_lock_flags(update: bool, reinstall: bool, frozen: bool) -> Option<Self> {
+        match (update, reinstall, frozen) {
+            (false, false, false) => Some(Self::Normal),
+            (true, false, false) => Some(Self::Update),
+            (false, true, false) => Some(Self::Reinstall),
+            (false, false, true) => Some(Self::Frozen),
+            _ => unreachable!(),
         }
     }
 
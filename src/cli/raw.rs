@@ -2,12 +2,14 @@
 
 use std::path::PathBuf;
 
+use clap::builder::FalseyValueParser;
 use clap::{ArgGroup, Parser};
 use clap_complete as complete;
 use url::Url;
 
 use crate::cli::color_choice::ColorChoice;
 use crate::config::{GistRepository, GitHubRepository, GitProtocol, Shell};
+use crate::lock::LogFormat;
 use crate::util::build;
 
 const HELP_TEMPLATE: &str = "\
@@ -60,6 +62,10 @@ pub struct RawOpt {
     #[clap(long, value_name = "PROFILE", env = "SHELDON_PROFILE")]
     pub profile: Option<String>,
 
+    /// Only use plugin sources that are already downloaded.
+    #[clap(long, env = "SHELDON_OFFLINE", value_parser = FalseyValueParser::new())]
+    pub offline: bool,
+
     /// The subcommand to run.
     #[clap(subcommand)]
     pub command: RawCommand,
@@ -89,13 +95,26 @@ pub enum RawCommand {
 
     /// Install the plugins sources and generate the lock file.
     Lock {
-        /// Update all plugin sources.
-        #[clap(long)]
-        update: bool,
+        /// Update all plugin sources, or only those of the given plugins.
+        #[clap(long, value_name = "NAME", num_args(0..))]
+        update: Option<Vec<String>>,
+
+        /// Print the commits that changed in updated Git sources: text or json.
+        #[clap(
+            long,
+            value_name = "FORMAT",
+            num_args(0..=1),
+            default_missing_value = "text"
+        )]
+        log: Option<LogFormat>,
 
         /// Reinstall all plugin sources.
         #[clap(long, conflicts_with = "update")]
         reinstall: bool,
+
+        /// Fail if the lock file is out of date, without changing anything.
+        #[clap(long, conflicts_with_all = &["update", "reinstall"])]
+        frozen: bool,
     },
 
     /// Generate and print out the script.
@@ -113,6 +132,16 @@ pub enum RawCommand {
         reinstall: bool,
     },
 
+    /// Show plugins that are behind upstream.
+    Outdated,
+
+    /// Restore a previous lock file and check out its plugin sources.
+    Rollback {
+        /// How many lock files to go back.
+        #[clap(long, value_name = "N", default_value_t = 1)]
+        to: usize,
+    },
+
     /// Generate completions for the given shell.
     Completions {
         /// The type of shell, accepted values are: bash, zsh.
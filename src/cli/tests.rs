@@ -6,6 +6,7 @@ use clap::error::ErrorKind;
 use pretty_assertions::assert_eq;
 
 use crate::cli::color_choice::ColorChoice;
+use crate::lock::LogFormat;
 
 fn setup() {
     for (k, _) in env::vars() {
@@ -70,9 +71,12 @@ fn raw_opt_no_options() {
             data_dir: None,
             config_file: None,
             profile: None,
+            offline: false,
             command: RawCommand::Lock {
-                update: false,
-                reinstall: false
+                update: None,
+                log: None,
+                reinstall: false,
+                frozen: false,
             },
         }
     );
@@ -95,6 +99,7 @@ fn raw_opt_options() {
             "/plugins.toml",
             "--profile",
             "profile",
+            "--offline",
             "lock",
         ]),
         RawOpt {
@@ -105,9 +110,12 @@ fn raw_opt_options() {
             data_dir: Some("/test".into()),
             config_file: Some("/plugins.toml".into()),
             profile: Some("profile".into()),
+            offline: true,
             command: RawCommand::Lock {
-                update: false,
-                reinstall: false
+                update: None,
+                log: None,
+                reinstall: false,
+                frozen: false,
             },
         }
     );
@@ -469,6 +477,37 @@ fn raw_opt_lock_help() {
     assert_eq!(err.kind(), ErrorKind::DisplayHelp);
 }
 
+#[test]
+fn raw_opt_lock_with_update() {
+    setup();
+    let update = |args: &[&str]| match raw_opt(args).command {
+        RawCommand::Lock { update, .. } => update,
+        command => panic!("unexpected command `{:?}`", command),
+    };
+    assert_eq!(update(&["lock"]), None);
+    assert_eq!(update(&["lock", "--update"]), Some(vec![]));
+    assert_eq!(
+        update(&["lock", "--update", "a", "b"]),
+        Some(vec_into!["a", "b"])
+    );
+}
+
+#[test]
+fn raw_opt_lock_with_log() {
+    setup();
+    let log = |args: &[&str]| match raw_opt(args).command {
+        RawCommand::Lock { log, .. } => log,
+        command => panic!("unexpected command `{:?}`", command),
+    };
+    assert_eq!(log(&["lock"]), None);
+    assert_eq!(log(&["lock", "--log"]), Some(LogFormat::Text));
+    assert_eq!(log(&["lock", "--log", "json"]), Some(LogFormat::Json));
+    assert_eq!(
+        raw_opt_err(&["lock", "--log", "yaml"]).kind(),
+        ErrorKind::ValueValidation
+    );
+}
+
 #[test]
 fn raw_opt_lock_with_update_and_reinstall_expect_conflict() {
     setup();
@@ -478,6 +517,15 @@ fn raw_opt_lock_with_update_and_reinstall_expect_conflict() {
     );
 }
 
+#[test]
+fn raw_opt_lock_with_frozen_and_update_expect_conflict() {
+    setup();
+    assert_eq!(
+        raw_opt_err(&["lock", "--frozen", "--update"]).kind(),
+        ErrorKind::ArgumentConflict
+    );
+}
+
 #[test]
 fn raw_opt_source_help() {
     setup();
@@ -9,28 +9,47 @@ use crate::config::{Config, Plugin, Source};
 use crate::context::Context;
 use crate::lock::source;
 
-/// Clean the clone and download directories.
+/// Clean the clone, download, and command output directories.
 pub fn clean(ctx: &Context, warnings: &mut Vec<Error>, config: &Config) -> Result<()> {
     let mut source_dirs = HashSet::new();
     let mut parent_dirs = HashSet::new();
     let mut files = HashSet::new();
+    let multi_ref = source::multi_ref_repos(&config.plugins);
 
     for plugin in &config.plugins {
         if let Plugin::External(plugin) = plugin {
             match &plugin.source {
-                Source::Git { url, .. } => {
-                    let dir = source::git_dir(ctx, url)?;
+                Source::Git { url, reference, .. } => {
+                    let dir = source::git_dir(ctx, url, reference.as_ref(), &multi_ref)?;
                     parent_dirs.extend(dir.ancestors().map(Path::to_path_buf));
                     source_dirs.insert(dir);
                 }
-                Source::Remote { url } => {
+                Source::Remote { url, .. } => {
                     let (dir, file) = source::remote_dir_and_file(ctx, url)?;
+                    files.insert(source::validators_file(&file));
                     files.insert(file);
                     parent_dirs.extend(dir.ancestors().map(Path::to_path_buf));
                 }
+                Source::Archive { url, .. } => {
+                    let (dir, file) = source::remote_dir_and_file(ctx, url)?;
+                    source_dirs.insert(source::archive::extract_dir(&file)?);
+                    files.insert(source::archive::stamp_file(&file));
+                    files.insert(file);
+                    parent_dirs.extend(dir.ancestors().map(Path::to_path_buf));
+                }
+                Source::GitHubRelease { url, .. } => {
+                    let dir = source::releases_dir(ctx, url)?;
+                    parent_dirs.extend(dir.ancestors().map(Path::to_path_buf));
+                    source_dirs.insert(dir);
+                }
                 Source::Local { .. } => {
                     // Don't remove local plugins!
                 }
+                Source::Command { command } => {
+                    let file = source::command_file(ctx, command);
+                    files.insert(source::stamp_file(&file));
+                    files.insert(file);
+                }
             }
         }
     }
@@ -51,6 +70,7 @@ pub fn clean(ctx: &Context, warnings: &mut Vec<Error>, config: &Config) -> Resul
 
     for entry in WalkDir::new(ctx.download_dir())
         .into_iter()
+        .filter_entry(|e| !source_dirs.contains(e.path()))
         .filter_map(result::Result::ok)
         .filter(|e| {
             let p = e.path();
@@ -62,6 +82,17 @@ pub fn clean(ctx: &Context, warnings: &mut Vec<Error>, config: &Config) -> Resul
         }
     }
 
+    for entry in WalkDir::new(source::command_dir(ctx))
+        .min_depth(1)
+        .into_iter()
+        .filter_map(result::Result::ok)
+        .filter(|e| !files.contains(e.path()))
+    {
+        if let Err(err) = remove_path(ctx, entry.path()) {
+            warnings.push(err);
+        }
+    }
+
     Ok(())
 }
 
@@ -14,7 +14,7 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
 use url::Url;
 
-use crate::config::{GitReference, Shell};
+use crate::config::{GitReference, HostOptions, Shell};
 
 /// The contents of the configuration file.
 #[derive(Debug, Default, Deserialize)]
@@ -27,6 +27,20 @@ pub struct RawConfig {
     pub matches: Option<Vec<String>>,
     /// The default list of template names to apply to each matched file.
     pub apply: Option<Vec<String>>,
+    /// The default number of commits of history to fetch for Git sources.
+    pub depth: Option<u32>,
+    /// The default object filter for Git sources.
+    pub filter: Option<String>,
+    /// The number of seconds to wait before a stalled transfer fails.
+    pub timeout: Option<u64>,
+    /// The number of times to retry a failed fetch or download.
+    pub retries: Option<u32>,
+    /// The number of seconds to wait before retrying.
+    pub backoff: Option<u64>,
+    /// A map of host to the options for requests to that host.
+    pub hosts: IndexMap<String, HostOptions>,
+    /// A map of URL prefix to the prefix to download from instead.
+    pub mirrors: IndexMap<String, String>,
     /// A map of name to template string.
     pub templates: IndexMap<String, String>,
     /// A map of name to plugin.
@@ -48,19 +62,42 @@ pub struct RawPlugin {
     pub github: Option<GitHubRepository>,
     /// A downloadable file.
     pub remote: Option<Url>,
+    /// A downloadable archive.
+    pub archive: Option<Url>,
+    /// A GitHub repository with release assets.
+    pub github_release: Option<GitHubRepository>,
+    /// The name pattern of the release asset to download.
+    pub asset: Option<String>,
     /// A local directory.
     pub local: Option<PathBuf>,
     /// An inline script.
     pub inline: Option<String>,
+    /// A command whose output is the script.
+    pub command: Option<String>,
     /// What protocol to use when cloning a repository.
     pub proto: Option<GitProtocol>,
     /// The Git reference to checkout.
     #[serde(flatten)]
     pub reference: Option<GitReference>,
+    /// The expected SHA-256 checksum of a downloadable file.
+    pub sha256: Option<String>,
+    /// The number of leading path components to strip when extracting an
+    /// archive.
+    pub strip_components: Option<usize>,
+    /// The number of commits of history to fetch for a Git repository.
+    pub depth: Option<u32>,
+    /// The object filter to use when fetching a Git repository.
+    pub filter: Option<String>,
     /// Which directory to use in this plugin.
     ///
     /// This directory can contain template parameters.
     pub dir: Option<String>,
+    /// Other paths in a Git repository to check out, in addition to `dir`.
+    ///
+    /// These paths can contain template parameters.
+    pub sparse: Option<Vec<String>>,
+    /// Whether to update the submodules of a Git repository, or which ones.
+    pub submodules: Option<Submodules>,
     /// Which files to use in this plugin's directory. If this is `None` then
     /// this will figured out based on the global `matches` field.
     ///
@@ -96,6 +133,16 @@ pub struct GitHubRepository {
     pub name: String,
 }
 
+/// Which submodules of a Git repository to update.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum Submodules {
+    /// Update all or none of the submodules.
+    Enabled(bool),
+    /// Only update the submodules at these paths.
+    Paths(Vec<String>),
+}
+
 /// The Git protocol.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GitProtocol {
@@ -393,6 +440,12 @@ mod tests {
         assert_eq!(test.g, GitReference::Rev(String::from("cd65e828")));
     }
 
+    #[test]
+    fn git_reference_deserialize_version() {
+        let test: TestGitReference = toml::from_str("version = '^1.4'").unwrap();
+        assert_eq!(test.g, GitReference::Version(String::from("^1.4")));
+    }
+
     #[derive(Debug, Deserialize)]
     struct TestGistRepository {
         g: GistRepository,
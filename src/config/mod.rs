@@ -9,6 +9,7 @@ mod profile;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::str;
+use std::time::Duration;
 
 use anyhow::{Context as ResultExt, Error, Result};
 use indexmap::IndexMap;
@@ -31,6 +32,8 @@ pub struct Config {
     pub apply: Option<Vec<String>>,
     /// A map of name to template string.
     pub templates: IndexMap<String, String>,
+    /// How to fetch and download plugin sources.
+    pub network: NetworkOptions,
     /// Each configured plugin.
     pub plugins: Vec<Plugin>,
 }
@@ -43,6 +46,7 @@ pub enum Shell {
 }
 
 /// A configured plugin.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq, Eq)]
 pub enum Plugin {
     External(ExternalPlugin),
@@ -58,6 +62,8 @@ pub struct ExternalPlugin {
     pub source: Source,
     /// Which directory to use in this plugin.
     pub dir: Option<String>,
+    /// Other paths in a Git repository that this plugin needs.
+    pub sparse: Option<Vec<String>>,
     /// What files to use in the plugin's directory.
     pub uses: Option<Vec<String>>,
     /// What templates to apply to each matched file.
@@ -73,11 +79,37 @@ pub enum Source {
     Git {
         url: Url,
         reference: Option<GitReference>,
+        options: GitOptions,
     },
     /// A remote file.
-    Remote { url: Url },
+    Remote {
+        url: Url,
+        /// The URL to download from instead of `url`.
+        mirror: Option<Url>,
+        sha256: Option<String>,
+    },
+    /// A remote archive that is extracted.
+    Archive {
+        url: Url,
+        /// The URL to download from instead of `url`.
+        mirror: Option<Url>,
+        sha256: Option<String>,
+        strip_components: usize,
+    },
+    /// An asset of a GitHub release, extracted if it is an archive.
+    GitHubRelease {
+        /// The GitHub API URL of the repository.
+        url: Url,
+        reference: Option<GitReference>,
+        /// The name pattern of the asset.
+        asset: String,
+        sha256: Option<String>,
+        strip_components: usize,
+    },
     /// A local directory.
     Local { dir: PathBuf },
+    /// The output of a command, cached when locking.
+    Command { command: String },
 }
 
 /// A Git reference.
@@ -90,10 +122,100 @@ pub enum GitReference {
     Rev(String),
     /// From a tag.
     Tag(String),
+    /// From the highest tag matching a semantic version requirement.
+    Version(String),
+}
+
+/// Options that control how a Git repository is cloned.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct GitOptions {
+    /// Only fetch this many commits of history.
+    pub depth: Option<u32>,
+    /// Only fetch the objects matching this filter, e.g. `blob:none`.
+    pub filter: Option<String>,
+    /// Only check out these paths.
+    ///
+    /// This is not configured directly, it is determined from the plugins
+    /// that use the source when locking.
+    pub sparse: Option<Vec<String>>,
+    /// The URL to clone and fetch from instead of the source URL.
+    pub mirror: Option<Url>,
+    /// Only update the submodules at these paths, if this is `None` then all
+    /// submodules are updated.
+    pub submodules: Option<Vec<String>>,
+}
+
+impl GitOptions {
+    /// Whether only part of the repository should be fetched.
+    pub fn is_shallow_or_partial(&self) -> bool {
+        self.depth.is_some() || self.filter.is_some()
+    }
+
+    /// Combine these options with those of another plugin that uses the same
+    /// clone, so that the clone has everything that both plugins need.
+    ///
+    /// The deepest history and all the submodules of both are fetched, and
+    /// objects are only filtered if both use the same filter.
+    pub fn merge(&mut self, other: &GitOptions) {
+        self.depth = self.depth.zip(other.depth).map(|(a, b)| a.max(b));
+        if self.filter != other.filter {
+            self.filter = None;
+        }
+        self.submodules = match (self.submodules.take(), &other.submodules) {
+            (Some(mut paths), Some(other)) => {
+                for path in other {
+                    if !paths.contains(path) {
+                        paths.push(path.clone());
+                    }
+                }
+                Some(paths)
+            }
+            _ => None,
+        };
+    }
+}
+
+/// Options that control how plugin sources are fetched and downloaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkOptions {
+    /// How long to wait to connect, or for more data when a transfer stalls.
+    pub timeout: Duration,
+    /// How many times to retry a failed fetch or download.
+    pub retries: u32,
+    /// How long to wait before the first retry, this doubles each retry.
+    pub backoff: Duration,
+    /// A map of host to the options for requests to that host.
+    pub hosts: IndexMap<String, HostOptions>,
+}
+
+/// Options for HTTP requests to a particular host.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostOptions {
+    /// The username to authenticate with, along with the token.
+    pub username: Option<String>,
+    /// The name of the environment variable that contains a token to
+    /// authenticate with.
+    pub token_env: Option<String>,
+    /// Extra headers to send.
+    pub headers: IndexMap<String, String>,
+    /// The SSH private key to authenticate with.
+    pub ssh_key: Option<PathBuf>,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            retries: 0,
+            backoff: Duration::from_secs(1),
+            hosts: IndexMap::new(),
+        }
+    }
 }
 
 /// An inline configured plugin.
-#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Serialize)]
 pub struct InlinePlugin {
     /// The name of this plugin.
     pub name: String,
@@ -2,13 +2,18 @@
 
 use std::str;
 use std::str::FromStr;
+use std::time::Duration;
 
 use anyhow::{anyhow, bail, Context as ResultExt, Error, Result};
 use indexmap::IndexMap;
+use semver::VersionReq;
 use url::Url;
 
-use crate::config::file::{GitProtocol, RawConfig, RawPlugin};
-use crate::config::{Config, ExternalPlugin, InlinePlugin, Plugin, Shell, Source};
+use crate::config::file::{GitProtocol, RawConfig, RawPlugin, Submodules};
+use crate::config::{
+    Config, ExternalPlugin, GitOptions, GitReference, InlinePlugin, NetworkOptions, Plugin, Shell,
+    Source,
+};
 use crate::util::TEMPLATE_ENGINE;
 
 /// The Gist domain host.
@@ -17,12 +22,22 @@ const GIST_HOST: &str = "gist.github.com";
 /// The GitHub domain host.
 const GITHUB_HOST: &str = "github.com";
 
+/// The GitHub API URL.
+const GITHUB_API_URL: &str = "https://api.github.com";
+
 /// Normalize a raw config from the file into a [`Config`].
 pub fn normalize(raw_config: RawConfig, warnings: &mut Vec<Error>) -> Result<Config> {
     let RawConfig {
         shell,
         matches,
         apply,
+        depth,
+        filter,
+        timeout,
+        retries,
+        backoff,
+        hosts,
+        mirrors,
         templates,
         plugins,
         rest,
@@ -42,15 +57,45 @@ pub fn normalize(raw_config: RawConfig, warnings: &mut Vec<Error>) -> Result<Con
     let shell = shell.unwrap_or_default();
 
     validate_template_names(shell, &apply, &templates)?;
+    validate_depth(depth)?;
+    if timeout == Some(0) {
+        bail!("the `timeout` field must be at least 1");
+    }
+    let network = {
+        let default = NetworkOptions::default();
+        NetworkOptions {
+            timeout: timeout.map_or(default.timeout, Duration::from_secs),
+            retries: retries.unwrap_or(default.retries),
+            backoff: backoff.map_or(default.backoff, Duration::from_secs),
+            hosts,
+        }
+    };
+    for (host, options) in &network.hosts {
+        validate_headers(&options.headers).with_context(|| format!("invalid host `{host}`"))?;
+    }
 
     // Normalize the plugins.
     let mut normalized_plugins = Vec::with_capacity(plugins.len());
 
     for (name, plugin) in plugins {
-        normalized_plugins.push(
-            normalize_plugin(plugin, name.clone(), shell, &templates, warnings)
-                .with_context(|| format!("failed to normalize plugin `{name}`"))?,
-        );
+        let mut plugin = normalize_plugin(plugin, name.clone(), shell, &templates, warnings)
+            .with_context(|| format!("failed to normalize plugin `{name}`"))?;
+        // Use the global Git options for anything the plugin doesn't set.
+        if let Plugin::External(ExternalPlugin {
+            source: Source::Git { options, .. },
+            ..
+        }) = &mut plugin
+        {
+            options.depth = options.depth.or(depth);
+            if options.filter.is_none() {
+                options.filter = filter.clone();
+            }
+        }
+        if let Plugin::External(ExternalPlugin { source, .. }) = &mut plugin {
+            apply_mirrors(source, &mirrors)
+                .with_context(|| format!("failed to normalize plugin `{name}`"))?;
+        }
+        normalized_plugins.push(plugin);
     }
 
     Ok(Config {
@@ -58,6 +103,7 @@ pub fn normalize(raw_config: RawConfig, warnings: &mut Vec<Error>) -> Result<Con
         matches,
         apply,
         templates,
+        network,
         plugins: normalized_plugins,
     })
 }
@@ -73,6 +119,7 @@ fn normalize_plugin(
     templates: &IndexMap<String, String>,
     warnings: &mut Vec<Error>,
 ) -> Result<Plugin> {
+    #[allow(clippy::large_enum_variant)]
     enum TempSource {
         External(Source),
         Inline(String),
@@ -83,11 +130,21 @@ fn normalize_plugin(
         gist,
         github,
         remote,
+        archive,
+        github_release,
+        asset,
         local,
         inline,
+        command,
         mut proto,
         reference,
+        sha256,
+        strip_components,
+        depth,
+        filter,
         dir,
+        sparse,
+        submodules,
         uses,
         apply,
         profiles,
@@ -113,13 +170,45 @@ fn normalize_plugin(
         warnings.push(anyhow!("unused config key: `plugins.{name}.{key}`"))
     });
 
-    let raw_source = match (git, gist, github, remote, local, inline) {
+    if let Some(GitReference::Version(req)) = &reference {
+        VersionReq::parse(req)
+            .with_context(|| format!("failed to parse version requirement `{req}`"))?;
+    }
+
+    validate_depth(depth)?;
+    let options = GitOptions {
+        depth,
+        filter: filter.clone(),
+        sparse: None,
+        mirror: None,
+        submodules: match &submodules {
+            None | Some(Submodules::Enabled(true)) => None,
+            Some(Submodules::Enabled(false)) => Some(Vec::new()),
+            Some(Submodules::Paths(paths)) => Some(paths.clone()),
+        },
+    };
+
+    let raw_source = match (
+        git,
+        gist,
+        github,
+        remote,
+        archive,
+        github_release,
+        local,
+        inline,
+        command,
+    ) {
         // `git` type
-        (Some(url), None, None, None, None, None) => {
-            TempSource::External(Source::Git { url, reference })
+        (Some(url), None, None, None, None, None, None, None, None) => {
+            TempSource::External(Source::Git {
+                url,
+                reference,
+                options,
+            })
         }
         // `gist` type
-        (None, Some(repository), None, None, None, None) => {
+        (None, Some(repository), None, None, None, None, None, None, None) => {
             let url_str = format!(
                 "{}{}/{}",
                 proto.unwrap_or(GitProtocol::Https).prefix(),
@@ -128,10 +217,14 @@ fn normalize_plugin(
             );
             let url = Url::parse(&url_str)
                 .with_context(|| format!("failed to construct Gist URL using `{repository}`"))?;
-            TempSource::External(Source::Git { url, reference })
+            TempSource::External(Source::Git {
+                url,
+                reference,
+                options,
+            })
         }
         // `github` type
-        (None, None, Some(repository), None, None, None) => {
+        (None, None, Some(repository), None, None, None, None, None, None) => {
             let url_str = format!(
                 "{}{}/{}",
                 proto.unwrap_or(GitProtocol::Https).prefix(),
@@ -140,15 +233,63 @@ fn normalize_plugin(
             );
             let url = Url::parse(&url_str)
                 .with_context(|| format!("failed to construct GitHub URL using `{repository}`"))?;
-            TempSource::External(Source::Git { url, reference })
+            TempSource::External(Source::Git {
+                url,
+                reference,
+                options,
+            })
         }
         // `remote` type
-        (None, None, None, Some(url), None, None) => TempSource::External(Source::Remote { url }),
+        (None, None, None, Some(url), None, None, None, None, None) => {
+            let sha256 = sha256.as_deref().map(validate_sha256).transpose()?;
+            TempSource::External(Source::Remote {
+                url,
+                mirror: None,
+                sha256,
+            })
+        }
+        // `archive` type
+        (None, None, None, None, Some(url), None, None, None, None) => {
+            let sha256 = sha256.as_deref().map(validate_sha256).transpose()?;
+            TempSource::External(Source::Archive {
+                url,
+                mirror: None,
+                sha256,
+                strip_components: strip_components.unwrap_or(0),
+            })
+        }
+        // `github_release` type
+        (None, None, None, None, None, Some(repository), None, None, None) => {
+            if let Some(GitReference::Branch(_) | GitReference::Rev(_)) = reference {
+                bail!("the `branch` and `rev` fields are not supported by GitHub release sources");
+            }
+            let url_str = format!("{GITHUB_API_URL}/repos/{repository}");
+            let url = Url::parse(&url_str).with_context(|| {
+                format!("failed to construct GitHub API URL using `{repository}`")
+            })?;
+            let asset = asset
+                .clone()
+                .context("the `asset` field is required for GitHub release sources")?;
+            let sha256 = sha256.as_deref().map(validate_sha256).transpose()?;
+            TempSource::External(Source::GitHubRelease {
+                url,
+                reference,
+                asset,
+                sha256,
+                strip_components: strip_components.unwrap_or(0),
+            })
+        }
         // `local` type
-        (None, None, None, None, Some(dir), None) => TempSource::External(Source::Local { dir }),
+        (None, None, None, None, None, None, Some(dir), None, None) => {
+            TempSource::External(Source::Local { dir })
+        }
         // `inline` type
-        (None, None, None, None, None, Some(raw)) => TempSource::Inline(raw),
-        (None, None, None, None, None, None) => {
+        (None, None, None, None, None, None, None, Some(raw), None) => TempSource::Inline(raw),
+        // `command` type
+        (None, None, None, None, None, None, None, None, Some(command)) => {
+            TempSource::External(Source::Command { command })
+        }
+        (None, None, None, None, None, None, None, None, None) => {
             bail!("plugin `{name}` has no source fields");
         }
         _ => {
@@ -158,12 +299,27 @@ fn normalize_plugin(
 
     match raw_source {
         TempSource::External(source) => {
-            if !source.is_git() && is_reference_some {
+            if !source.is_git() && !source.is_github_release() && is_reference_some {
                 bail!(
-                    "the `branch`, `tag`, and `rev` fields are not supported by this plugin type"
+                    "the `branch`, `tag`, `rev`, and `version` fields are not supported by this \
+                     plugin type"
                 );
             } else if proto.is_some() && !is_gist_or_github {
                 bail!("the `proto` field is not supported by this plugin type");
+            } else if sha256.is_some() && !source.is_download() {
+                bail!("the `sha256` field is not supported by this plugin type");
+            } else if strip_components.is_some() && !source.is_archive() {
+                bail!("the `strip_components` field is not supported by this plugin type");
+            } else if asset.is_some() && !source.is_github_release() {
+                bail!("the `asset` field is not supported by this plugin type");
+            } else if depth.is_some() && !source.is_git() {
+                bail!("the `depth` field is not supported by this plugin type");
+            } else if filter.is_some() && !source.is_git() {
+                bail!("the `filter` field is not supported by this plugin type");
+            } else if sparse.is_some() && !source.is_git() {
+                bail!("the `sparse` field is not supported by this plugin type");
+            } else if submodules.is_some() && !source.is_git() {
+                bail!("the `submodules` field is not supported by this plugin type");
             }
 
             validate_template_names(shell, &apply, templates)?;
@@ -172,6 +328,7 @@ fn normalize_plugin(
                 name,
                 source,
                 dir,
+                sparse,
                 uses,
                 apply,
                 profiles,
@@ -180,8 +337,18 @@ fn normalize_plugin(
         TempSource::Inline(raw) => {
             let unsupported = [
                 ("`proto` field is", proto.is_some()),
-                ("`branch`, `tag`, and `rev` fields are", is_reference_some),
+                (
+                    "`branch`, `tag`, `rev`, and `version` fields are",
+                    is_reference_some,
+                ),
+                ("`sha256` field is", sha256.is_some()),
+                ("`strip_components` field is", strip_components.is_some()),
+                ("`asset` field is", asset.is_some()),
+                ("`depth` field is", depth.is_some()),
+                ("`filter` field is", filter.is_some()),
                 ("`dir` field is", dir.is_some()),
+                ("`sparse` field is", sparse.is_some()),
+                ("`submodules` field is", submodules.is_some()),
                 ("`use` field is", uses.is_some()),
                 ("`apply` field is", apply.is_some()),
             ];
@@ -214,6 +381,24 @@ impl Source {
     fn is_git(&self) -> bool {
         matches!(*self, Self::Git { .. })
     }
+
+    /// Whether this is a remote, archive, or GitHub release source.
+    fn is_download(&self) -> bool {
+        matches!(
+            *self,
+            Self::Remote { .. } | Self::Archive { .. } | Self::GitHubRelease { .. }
+        )
+    }
+
+    /// Whether this is an archive or GitHub release source.
+    fn is_archive(&self) -> bool {
+        matches!(*self, Self::Archive { .. } | Self::GitHubRelease { .. })
+    }
+
+    /// Whether this is a GitHub release source.
+    fn is_github_release(&self) -> bool {
+        matches!(*self, Self::GitHubRelease { .. })
+    }
 }
 
 /// Try and pop the TOML value from the table.
@@ -245,6 +430,64 @@ where
     }
 }
 
+/// Check that the given string is a SHA-256 checksum and normalize it.
+fn validate_sha256(s: &str) -> Result<String> {
+    if s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(s.to_ascii_lowercase())
+    } else {
+        bail!("`{s}` is not a valid SHA-256 checksum, expected 64 hexadecimal characters")
+    }
+}
+
+/// Set the mirror of a source whose URL starts with one of the mirrored
+/// prefixes.
+///
+/// The source URL itself is left alone so that the directory it is cloned or
+/// downloaded to doesn't change. If more than one prefix matches then the
+/// longest one is used.
+fn apply_mirrors(source: &mut Source, mirrors: &IndexMap<String, String>) -> Result<()> {
+    let (url, mirror) = match source {
+        Source::Git { url, options, .. } => (&*url, &mut options.mirror),
+        Source::Remote { url, mirror, .. } | Source::Archive { url, mirror, .. } => (&*url, mirror),
+        Source::GitHubRelease { .. } | Source::Local { .. } | Source::Command { .. } => {
+            return Ok(())
+        }
+    };
+    let found = mirrors
+        .iter()
+        .filter(|(prefix, _)| url.as_str().starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len());
+    if let Some((prefix, replacement)) = found {
+        let rewritten = format!("{replacement}{}", &url.as_str()[prefix.len()..]);
+        let rewritten = Url::parse(&rewritten)
+            .with_context(|| format!("failed to rewrite URL `{url}` using mirror `{prefix}`"))?;
+        *mirror = Some(rewritten);
+    }
+    Ok(())
+}
+
+/// Check that the headers can be sent in an HTTP request.
+fn validate_headers(headers: &IndexMap<String, String>) -> Result<()> {
+    for (name, value) in headers {
+        let is_token = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+        if name.is_empty() || !name.chars().all(is_token) {
+            bail!("invalid header name `{name}`");
+        }
+        if value.contains(['\r', '\n']) {
+            bail!("invalid value for header `{name}`");
+        }
+    }
+    Ok(())
+}
+
+/// Check that a Git clone depth fetches at least one commit.
+fn validate_depth(depth: Option<u32>) -> Result<()> {
+    if depth == Some(0) {
+        bail!("the `depth` field must be at least 1");
+    }
+    Ok(())
+}
+
 /// Check whether the specifed templates actually exist.
 fn validate_template_names(
     shell: Shell,
@@ -313,8 +556,10 @@ mod tests {
             source: Source::Git {
                 url: url.clone(),
                 reference: None,
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
@@ -345,8 +590,10 @@ mod tests {
                 )
                 .unwrap(),
                 reference: None,
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
@@ -380,8 +627,10 @@ mod tests {
                 url: Url::parse("https://gist.github.com/579d02802b1cc17baed07753d09f5009")
                     .unwrap(),
                 reference: None,
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
@@ -412,8 +661,10 @@ mod tests {
                 )
                 .unwrap(),
                 reference: None,
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
@@ -446,8 +697,10 @@ mod tests {
             source: Source::Git {
                 url: Url::parse("git://github.com/rossmacarthur/sheldon-test").unwrap(),
                 reference: None,
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
@@ -479,8 +732,10 @@ mod tests {
             source: Source::Git {
                 url: Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
                 reference: None,
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
@@ -511,8 +766,10 @@ mod tests {
             source: Source::Git {
                 url: Url::parse("ssh://git@github.com/rossmacarthur/sheldon-test").unwrap(),
                 reference: None,
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
@@ -544,8 +801,13 @@ mod tests {
                 .unwrap();
         let expected = Plugin::External(ExternalPlugin {
             name: name.clone(),
-            source: Source::Remote { url: url.clone() },
+            source: Source::Remote {
+                url: url.clone(),
+                mirror: None,
+                sha256: None,
+            },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
@@ -587,7 +849,28 @@ mod tests {
         .unwrap_err();
         assert_eq!(
             err.to_string(),
-            "the `branch`, `tag`, and `rev` fields are not supported by this plugin type"
+            "the `branch`, `tag`, `rev`, and `version` fields are not supported by this plugin type"
+        );
+    }
+
+    #[test]
+    fn normalize_plugin_github_with_invalid_version() {
+        let raw_plugin = RawPlugin {
+            github: Some("rossmacarthur/sheldon-test".parse().unwrap()),
+            reference: Some(GitReference::Version("one point four".to_string())),
+            ..Default::default()
+        };
+        let err = normalize_plugin(
+            raw_plugin,
+            "test".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to parse version requirement `one point four`"
         );
     }
 
@@ -617,6 +900,236 @@ mod tests {
         );
     }
 
+    #[test]
+    fn normalize_plugin_remote_with_sha256() {
+        let raw_plugin = RawPlugin {
+            remote: Some(
+                Url::parse(
+                    "https://github.com/rossmacarthur/sheldon-test/blob/master/test.plugin.zsh",
+                )
+                .unwrap(),
+            ),
+            sha256: Some(
+                "F9C4C77BAA3828004EE54B8A4F2DB2E88ED44A6237A493965BF551FAC0FCB62D".to_string(),
+            ),
+            ..Default::default()
+        };
+        let plugin = normalize_plugin(
+            raw_plugin,
+            "test".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        match plugin {
+            Plugin::External(ExternalPlugin {
+                source: Source::Remote { sha256, .. },
+                ..
+            }) => assert_eq!(
+                sha256.as_deref(),
+                Some("f9c4c77baa3828004ee54b8a4f2db2e88ed44a6237a493965bf551fac0fcb62d")
+            ),
+            plugin => panic!("unexpected plugin `{:?}`", plugin),
+        }
+    }
+
+    #[test]
+    fn normalize_plugin_remote_with_invalid_sha256() {
+        let raw_plugin = RawPlugin {
+            remote: Some(
+                Url::parse(
+                    "https://github.com/rossmacarthur/sheldon-test/blob/master/test.plugin.zsh",
+                )
+                .unwrap(),
+            ),
+            sha256: Some("f9c4c77".to_string()),
+            ..Default::default()
+        };
+        let err = normalize_plugin(
+            raw_plugin,
+            "test".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`f9c4c77` is not a valid SHA-256 checksum, expected 64 hexadecimal characters"
+        );
+    }
+
+    #[test]
+    fn normalize_plugin_git_with_sha256() {
+        let raw_plugin = RawPlugin {
+            github: Some("rossmacarthur/sheldon-test".parse().unwrap()),
+            sha256: Some(
+                "f9c4c77baa3828004ee54b8a4f2db2e88ed44a6237a493965bf551fac0fcb62d".to_string(),
+            ),
+            ..Default::default()
+        };
+        let err = normalize_plugin(
+            raw_plugin,
+            "test".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the `sha256` field is not supported by this plugin type"
+        );
+    }
+
+    #[test]
+    fn normalize_plugin_archive() {
+        let name = "test".to_string();
+        let url = Url::parse("https://github.com/rossmacarthur/sheldon-test/archive/v0.1.0.tar.gz")
+            .unwrap();
+        let expected = Plugin::External(ExternalPlugin {
+            name: name.clone(),
+            source: Source::Archive {
+                url: url.clone(),
+                mirror: None,
+                sha256: None,
+                strip_components: 1,
+            },
+            dir: None,
+            sparse: None,
+            uses: None,
+            apply: None,
+            profiles: None,
+        });
+        let raw_plugin = RawPlugin {
+            archive: Some(url),
+            strip_components: Some(1),
+            ..Default::default()
+        };
+        let plugin = normalize_plugin(
+            raw_plugin,
+            name,
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(plugin, expected);
+    }
+
+    #[test]
+    fn normalize_plugin_remote_with_strip_components() {
+        let raw_plugin = RawPlugin {
+            remote: Some(
+                Url::parse(
+                    "https://github.com/rossmacarthur/sheldon-test/blob/master/test.plugin.zsh",
+                )
+                .unwrap(),
+            ),
+            strip_components: Some(1),
+            ..Default::default()
+        };
+        let err = normalize_plugin(
+            raw_plugin,
+            "test".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the `strip_components` field is not supported by this plugin type"
+        );
+    }
+
+    #[test]
+    fn normalize_plugin_github_release() {
+        let name = "fzf".to_string();
+        let expected = Plugin::External(ExternalPlugin {
+            name: name.clone(),
+            source: Source::GitHubRelease {
+                url: Url::parse("https://api.github.com/repos/junegunn/fzf").unwrap(),
+                reference: Some(GitReference::Version("0.35".to_string())),
+                asset: "fzf-*-linux_amd64.tar.gz".to_string(),
+                sha256: None,
+                strip_components: 0,
+            },
+            dir: None,
+            sparse: None,
+            uses: None,
+            apply: None,
+            profiles: None,
+        });
+        let raw_plugin = RawPlugin {
+            github_release: Some(GitHubRepository {
+                owner: "junegunn".to_string(),
+                name: "fzf".to_string(),
+            }),
+            reference: Some(GitReference::Version("0.35".to_string())),
+            asset: Some("fzf-*-linux_amd64.tar.gz".to_string()),
+            ..Default::default()
+        };
+        let plugin = normalize_plugin(
+            raw_plugin,
+            name,
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(plugin, expected);
+    }
+
+    #[test]
+    fn normalize_plugin_github_release_without_asset() {
+        let raw_plugin = RawPlugin {
+            github_release: Some(GitHubRepository {
+                owner: "junegunn".to_string(),
+                name: "fzf".to_string(),
+            }),
+            ..Default::default()
+        };
+        let err = normalize_plugin(
+            raw_plugin,
+            "fzf".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the `asset` field is required for GitHub release sources"
+        );
+    }
+
+    #[test]
+    fn normalize_plugin_github_release_with_branch() {
+        let raw_plugin = RawPlugin {
+            github_release: Some(GitHubRepository {
+                owner: "junegunn".to_string(),
+                name: "fzf".to_string(),
+            }),
+            reference: Some(GitReference::Branch("master".to_string())),
+            asset: Some("fzf-*".to_string()),
+            ..Default::default()
+        };
+        let err = normalize_plugin(
+            raw_plugin,
+            "fzf".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the `branch` and `rev` fields are not supported by GitHub release sources"
+        );
+    }
+
     #[test]
     fn normalize_plugin_local() {
         let name = "test".to_string();
@@ -626,6 +1139,7 @@ mod tests {
                 dir: "/home/temp".into(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
@@ -645,6 +1159,35 @@ mod tests {
         assert_eq!(plugin, expected);
     }
 
+    #[test]
+    fn normalize_plugin_command() {
+        let name = "test".to_string();
+        let expected = Plugin::External(ExternalPlugin {
+            name: name.clone(),
+            source: Source::Command {
+                command: "zoxide init zsh".into(),
+            },
+            dir: None,
+            sparse: None,
+            uses: None,
+            apply: None,
+            profiles: None,
+        });
+        let raw_plugin = RawPlugin {
+            command: Some("zoxide init zsh".into()),
+            ..Default::default()
+        };
+        let plugin = normalize_plugin(
+            raw_plugin,
+            name,
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(plugin, expected);
+    }
+
     #[test]
     fn normalize_plugin_inline() {
         let name = "test".to_string();
@@ -709,4 +1252,312 @@ mod tests {
         .unwrap_err();
         assert_eq!(err.to_string(), "unknown template `test`");
     }
+
+    #[test]
+    fn normalize_plugin_git_with_depth_and_filter() {
+        let url = Url::parse("https://github.com/ohmyzsh/ohmyzsh").unwrap();
+        let raw_plugin = RawPlugin {
+            git: Some(url.clone()),
+            depth: Some(1),
+            filter: Some("blob:none".to_string()),
+            ..Default::default()
+        };
+        let plugin = normalize_plugin(
+            raw_plugin,
+            "ohmyzsh".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            plugin,
+            Plugin::External(ExternalPlugin {
+                name: "ohmyzsh".to_string(),
+                source: Source::Git {
+                    url,
+                    reference: None,
+                    options: GitOptions {
+                        depth: Some(1),
+                        filter: Some("blob:none".to_string()),
+                        sparse: None,
+                        mirror: None,
+                        submodules: None,
+                    },
+                },
+                dir: None,
+                sparse: None,
+                uses: None,
+                apply: None,
+                profiles: None,
+            })
+        );
+    }
+
+    #[test]
+    fn normalize_plugin_git_with_zero_depth() {
+        let raw_plugin = RawPlugin {
+            git: Some(Url::parse("https://github.com/ohmyzsh/ohmyzsh").unwrap()),
+            depth: Some(0),
+            ..Default::default()
+        };
+        let err = normalize_plugin(
+            raw_plugin,
+            "ohmyzsh".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "the `depth` field must be at least 1");
+    }
+
+    #[test]
+    fn normalize_plugin_remote_with_depth() {
+        let raw_plugin = RawPlugin {
+            remote: Some(Url::parse("https://example.com/test.plugin.zsh").unwrap()),
+            depth: Some(1),
+            ..Default::default()
+        };
+        let err = normalize_plugin(
+            raw_plugin,
+            "test".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the `depth` field is not supported by this plugin type"
+        );
+    }
+
+    #[test]
+    fn normalize_global_depth_and_filter() {
+        let raw_config: RawConfig = toml::from_str(
+            r#"
+            depth = 1
+            filter = "blob:none"
+
+            [plugins.ohmyzsh]
+            github = "ohmyzsh/ohmyzsh"
+
+            [plugins.sheldon-test]
+            github = "rossmacarthur/sheldon-test"
+            depth = 10
+            "#,
+        )
+        .unwrap();
+        let config = normalize(raw_config, &mut Vec::new()).unwrap();
+        let options: Vec<_> = config
+            .plugins
+            .iter()
+            .map(|plugin| match plugin {
+                Plugin::External(ExternalPlugin {
+                    source: Source::Git { options, .. },
+                    ..
+                }) => options.clone(),
+                _ => panic!("expected a Git plugin"),
+            })
+            .collect();
+        assert_eq!(
+            options,
+            [
+                GitOptions {
+                    depth: Some(1),
+                    filter: Some("blob:none".to_string()),
+                    sparse: None,
+                    mirror: None,
+                    submodules: None,
+                },
+                GitOptions {
+                    depth: Some(10),
+                    filter: Some("blob:none".to_string()),
+                    sparse: None,
+                    mirror: None,
+                    submodules: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize_plugin_submodules() {
+        let raw_config: RawConfig = toml::from_str(
+            r#"
+            [plugins.all]
+            github = "owner/all"
+            submodules = true
+
+            [plugins.none]
+            github = "owner/none"
+            submodules = false
+
+            [plugins.some]
+            github = "owner/some"
+            submodules = ["vendor/lib"]
+            "#,
+        )
+        .unwrap();
+        let config = normalize(raw_config, &mut Vec::new()).unwrap();
+        let submodules: Vec<_> = config
+            .plugins
+            .iter()
+            .map(|plugin| match plugin {
+                Plugin::External(ExternalPlugin {
+                    source: Source::Git { options, .. },
+                    ..
+                }) => options.submodules.clone(),
+                _ => panic!("expected a Git plugin"),
+            })
+            .collect();
+        assert_eq!(
+            submodules,
+            [None, Some(vec![]), Some(vec!["vendor/lib".to_string()])]
+        );
+
+        let raw_plugin = RawPlugin {
+            remote: Some(Url::parse("https://example.com/plugin.zsh").unwrap()),
+            submodules: Some(Submodules::Enabled(false)),
+            ..Default::default()
+        };
+        let err = normalize_plugin(
+            raw_plugin,
+            "test".to_string(),
+            Shell::default(),
+            &IndexMap::new(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the `submodules` field is not supported by this plugin type"
+        );
+    }
+
+    #[test]
+    fn normalize_mirrors() {
+        let raw_config: RawConfig = toml::from_str(
+            r#"
+            [mirrors]
+            "https://github.com/" = "https://git.internal.corp/github/"
+            "https://github.com/rossmacarthur/" = "https://git.internal.corp/ross/"
+
+            [plugins.ohmyzsh]
+            github = "ohmyzsh/ohmyzsh"
+
+            [plugins.sheldon-test]
+            github = "rossmacarthur/sheldon-test"
+
+            [plugins.license]
+            remote = "https://github.com/rossmacarthur/sheldon/raw/0.3.0/LICENSE-MIT"
+
+            [plugins.example]
+            remote = "https://example.com/plugin.zsh"
+            "#,
+        )
+        .unwrap();
+        let config = normalize(raw_config, &mut Vec::new()).unwrap();
+        let sources: Vec<_> = config
+            .plugins
+            .iter()
+            .map(|plugin| match plugin {
+                Plugin::External(ExternalPlugin {
+                    source: Source::Git { url, options, .. },
+                    ..
+                }) => (url.to_string(), options.mirror.as_ref().map(Url::to_string)),
+                Plugin::External(ExternalPlugin {
+                    source: Source::Remote { url, mirror, .. },
+                    ..
+                }) => (url.to_string(), mirror.as_ref().map(Url::to_string)),
+                _ => panic!("expected a Git or remote plugin"),
+            })
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                (
+                    "https://github.com/ohmyzsh/ohmyzsh".to_string(),
+                    Some("https://git.internal.corp/github/ohmyzsh/ohmyzsh".to_string())
+                ),
+                (
+                    "https://github.com/rossmacarthur/sheldon-test".to_string(),
+                    Some("https://git.internal.corp/ross/sheldon-test".to_string())
+                ),
+                (
+                    "https://github.com/rossmacarthur/sheldon/raw/0.3.0/LICENSE-MIT".to_string(),
+                    Some(
+                        "https://git.internal.corp/ross/sheldon/raw/0.3.0/LICENSE-MIT".to_string()
+                    )
+                ),
+                ("https://example.com/plugin.zsh".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize_mirrors_invalid_url() {
+        let raw_config: RawConfig = toml::from_str(
+            r#"
+            [mirrors]
+            "https://github.com/" = "not a url/"
+
+            [plugins.ohmyzsh]
+            github = "ohmyzsh/ohmyzsh"
+            "#,
+        )
+        .unwrap();
+        let err = normalize(raw_config, &mut Vec::new()).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "failed to normalize plugin `ohmyzsh`: failed to rewrite URL \
+             `https://github.com/ohmyzsh/ohmyzsh` using mirror `https://github.com/`: relative \
+             URL without a base"
+        );
+    }
+
+    #[test]
+    fn normalize_network_options() {
+        let raw_config: RawConfig = toml::from_str(
+            r#"
+            timeout = 10
+            retries = 3
+            "#,
+        )
+        .unwrap();
+        let config = normalize(raw_config, &mut Vec::new()).unwrap();
+        assert_eq!(
+            config.network,
+            NetworkOptions {
+                timeout: Duration::from_secs(10),
+                retries: 3,
+                backoff: Duration::from_secs(1),
+                hosts: IndexMap::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn normalize_zero_timeout() {
+        let raw_config: RawConfig = toml::from_str("timeout = 0").unwrap();
+        let err = normalize(raw_config, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "the `timeout` field must be at least 1");
+    }
+
+    #[test]
+    fn normalize_hosts_invalid_header() {
+        let raw_config: RawConfig = toml::from_str(
+            r#"
+            [hosts."gitlab.corp.com"]
+            headers = { "Private Token" = "abc" }
+            "#,
+        )
+        .unwrap();
+        let err = normalize(raw_config, &mut Vec::new()).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "invalid host `gitlab.corp.com`: invalid header name `Private Token`"
+        );
+    }
 }
@@ -11,8 +11,9 @@ use serde::{Deserialize, Serialize};
 pub use yansi::Color;
 use yansi::Paint;
 
+use crate::config::NetworkOptions;
 use crate::context::message::{Message, ToMessage};
-use crate::lock::LockMode;
+use crate::lock::{LockMode, LogFormat};
 
 #[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Context {
@@ -33,6 +34,19 @@ pub struct Context {
     pub output: Output,
     #[serde(skip)]
     pub lock_mode: Option<LockMode>,
+    /// The plugins to update when updating, if empty all plugins are updated.
+    #[serde(skip)]
+    pub update_plugins: Vec<String>,
+    /// How to print the commits that changed in updated Git sources.
+    #[serde(skip)]
+    pub update_log: Option<LogFormat>,
+    /// Whether to only use plugin sources that are already on disk.
+    #[serde(skip)]
+    pub offline: bool,
+    /// How to fetch and download plugin sources, this is set from the config
+    /// file when locking.
+    #[serde(skip)]
+    pub network: NetworkOptions,
 }
 
 /// The output style.
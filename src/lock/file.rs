@@ -1,6 +1,7 @@
 //! The raw lock file.
 
 use std::fs;
+use std::mem;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context as ResultExt, Error, Result};
@@ -9,6 +10,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::config::InlinePlugin;
 use crate::context::Context;
+use crate::lock::schema::SCHEMA;
+use crate::lock::{history, Update};
 
 /// A locked `Config`.
 #[derive(Debug, Deserialize, Serialize)]
@@ -26,10 +29,22 @@ pub struct LockedConfig {
     /// Any errors that occurred while generating this `LockedConfig`.
     #[serde(skip)]
     pub errors: Vec<Error>,
+    /// The Git sources that were checked out at a different commit.
+    #[serde(skip)]
+    pub updates: Vec<Update>,
+}
+
+/// A `LockedConfig` as it is written to the lock file.
+#[derive(Serialize)]
+struct Versioned<'a> {
+    /// The lock file schema version.
+    schema: u32,
+    #[serde(flatten)]
+    config: &'a LockedConfig,
 }
 
 /// A locked `Plugin`.
-#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum LockedPlugin {
     External(LockedExternalPlugin),
@@ -37,12 +52,18 @@ pub enum LockedPlugin {
 }
 
 /// A locked `ExternalPlugin`.
-#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct LockedExternalPlugin {
     /// The name of this plugin.
     pub name: String,
     /// The directory that this plugin's source resides in.
     pub source_dir: PathBuf,
+    /// The Git commit that the source directory is checked out at.
+    pub commit: Option<String>,
+    /// The Git tag that was chosen for a version requirement.
+    pub tag: Option<String>,
+    /// The SHA-256 checksum of the downloaded file.
+    pub sha256: Option<String>,
     /// The directory that this plugin resides in (inside the source directory).
     pub plugin_dir: Option<PathBuf>,
     /// The files to use in the plugin directory.
@@ -53,6 +74,9 @@ pub struct LockedExternalPlugin {
 
 impl LockedConfig {
     /// Write a `LockedConfig` config to the given path.
+    ///
+    /// If this changes an existing lock file then the previous version is kept
+    /// in the lock file history.
     pub fn to_path<P>(&self, path: P) -> Result<()>
     where
         P: AsRef<Path>,
@@ -63,11 +87,92 @@ impl LockedConfig {
                 format!("failed to create parent directory `{}`", parent.display())
             })?;
         }
-        fs::write(
-            path,
-            toml::to_string(&self).context("failed to serialize locked config")?,
-        )
-        .with_context(|| format!("failed to write locked config to `{}`", path.display()))?;
+        let contents = toml::to_string(&Versioned {
+            schema: SCHEMA,
+            config: &self.to_portable(),
+        })
+        .context("failed to serialize locked config")?;
+        history::save(path, &contents).context("failed to save previous lock file")?;
+        fs::write(path, contents)
+            .with_context(|| format!("failed to write locked config to `{}`", path.display()))?;
         Ok(())
     }
+
+    /// Returns a copy of this `LockedConfig` with portable paths.
+    ///
+    /// Paths inside the data directory are made relative to it and any other
+    /// paths inside the home directory start with a tilde. This means the same
+    /// lock file is valid for anyone using the same directory layout.
+    fn to_portable(&self) -> Self {
+        let ctx = Context {
+            home: PathBuf::from("~"),
+            config_dir: tilde_path(&self.ctx, &self.ctx.config_dir),
+            data_dir: tilde_path(&self.ctx, &self.ctx.data_dir),
+            config_file: tilde_path(&self.ctx, &self.ctx.config_file),
+            ..self.ctx.clone()
+        };
+        let mut plugins = self.plugins.clone();
+        for plugin in &mut plugins {
+            if let LockedPlugin::External(plugin) = plugin {
+                plugin.map_paths(|path| match path.strip_prefix(&self.ctx.data_dir) {
+                    Ok(p) => p.to_path_buf(),
+                    Err(_) => tilde_path(&self.ctx, &path),
+                });
+            }
+        }
+        Self {
+            ctx,
+            plugins,
+            templates: self.templates.clone(),
+            errors: Vec::new(),
+            updates: Vec::new(),
+        }
+    }
+
+    /// Resolve the portable paths read from a lock file using the given home
+    /// directory.
+    ///
+    /// Lock files written by older versions only contain absolute paths, these
+    /// are left as is.
+    pub fn resolve_paths(&mut self, home: &Path) {
+        let ctx = &mut self.ctx;
+        if ctx.home == Path::new("~") {
+            ctx.home = home.to_path_buf();
+        }
+        ctx.config_dir = ctx.expand_tilde(ctx.config_dir.clone());
+        ctx.data_dir = ctx.expand_tilde(ctx.data_dir.clone());
+        ctx.config_file = ctx.expand_tilde(ctx.config_file.clone());
+        for plugin in &mut self.plugins {
+            if let LockedPlugin::External(plugin) = plugin {
+                plugin.map_paths(|path| {
+                    if path.is_relative() && !path.starts_with("~") {
+                        ctx.data_dir.join(path)
+                    } else {
+                        ctx.expand_tilde(path)
+                    }
+                });
+            }
+        }
+    }
+}
+
+impl LockedExternalPlugin {
+    /// Apply the given function to each path in this plugin.
+    fn map_paths<F>(&mut self, f: F)
+    where
+        F: Fn(PathBuf) -> PathBuf,
+    {
+        self.source_dir = f(mem::take(&mut self.source_dir));
+        self.plugin_dir = self.plugin_dir.take().map(&f);
+        self.files = mem::take(&mut self.files).into_iter().map(&f).collect();
+    }
+}
+
+/// Replaces the home directory in the given path with a tilde.
+fn tilde_path(ctx: &Context, path: &Path) -> PathBuf {
+    if path == ctx.home {
+        PathBuf::from("~")
+    } else {
+        ctx.replace_home(path)
+    }
 }
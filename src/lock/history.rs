@@ -0,0 +1,178 @@
+//! Previous lock files.
+//!
+//! Whenever the lock file changes, the previous version is kept in a `history`
+//! directory next to it, named after the lock file with the time it was
+//! replaced appended, e.g. `history/plugins.lock.1665000000`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context as ResultExt, Result};
+
+/// The number of previous lock files to keep.
+const HISTORY_SIZE: usize = 10;
+
+/// Save the current lock file at the given path to the history directory, if
+/// it differs from the new contents.
+pub fn save(path: &Path, contents: &str) -> Result<()> {
+    let previous = match fs::read_to_string(path) {
+        Ok(previous) if previous != contents => previous,
+        _ => return Ok(()),
+    };
+    let dir = history_dir(path);
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create directory `{}`", dir.display()))?;
+    let mut timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("system time is before the Unix epoch")?
+        .as_secs();
+    // Don't overwrite a lock file that was saved within the same second.
+    let mut history_path = dir.join(format!("{}.{timestamp}", file_name(path)));
+    while history_path.exists() {
+        timestamp += 1;
+        history_path = dir.join(format!("{}.{timestamp}", file_name(path)));
+    }
+    fs::write(&history_path, previous)
+        .with_context(|| format!("failed to write `{}`", history_path.display()))?;
+
+    for old in list(path)?.into_iter().skip(HISTORY_SIZE) {
+        fs::remove_file(&old).with_context(|| format!("failed to remove `{}`", old.display()))?;
+    }
+    Ok(())
+}
+
+/// Restore the `n`th previous lock file, where `1` is the most recent.
+///
+/// The current lock file is saved to the history directory so that the
+/// rollback itself can be undone. Returns the Unix timestamp of when the
+/// restored lock file was replaced.
+pub fn rollback(path: &Path, n: usize) -> Result<u64> {
+    if n == 0 {
+        bail!("the number of lock files to go back must be at least 1");
+    }
+    let history = list(path)?;
+    let history_path = match history.into_iter().nth(n - 1) {
+        Some(p) => p,
+        None => bail!("there is no lock file {n} versions ago"),
+    };
+    let timestamp = timestamp(path, &history_path).unwrap();
+    let contents = fs::read_to_string(&history_path)
+        .with_context(|| format!("failed to read `{}`", history_path.display()))?;
+    fs::remove_file(&history_path)
+        .with_context(|| format!("failed to remove `{}`", history_path.display()))?;
+    save(path, &contents)?;
+    fs::write(path, contents)
+        .with_context(|| format!("failed to write locked config to `{}`", path.display()))?;
+    Ok(timestamp)
+}
+
+/// Returns the previous lock files for the given lock file path, most recent
+/// first.
+fn list(path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = history_dir(path);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut history = Vec::new();
+    for entry in entries {
+        let entry =
+            entry.with_context(|| format!("failed to read directory `{}`", dir.display()))?;
+        let history_path = entry.path();
+        if let Some(timestamp) = timestamp(path, &history_path) {
+            history.push((timestamp, history_path));
+        }
+    }
+    history.sort_by(|a, b| b.cmp(a));
+    Ok(history.into_iter().map(|(_, p)| p).collect())
+}
+
+/// Returns the time that a previous lock file was replaced, from its name.
+fn timestamp(path: &Path, history_path: &Path) -> Option<u64> {
+    let prefix = format!("{}.", file_name(path));
+    history_path
+        .file_name()?
+        .to_str()?
+        .strip_prefix(&prefix)?
+        .parse()
+        .ok()
+}
+
+fn history_dir(path: &Path) -> PathBuf {
+    path.parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join("history")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Unit tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_save_and_rollback() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let path = temp.path().join("plugins.lock");
+
+        // Nothing to save yet.
+        save(&path, "a").unwrap();
+        assert!(list(&path).unwrap().is_empty());
+
+        // Unchanged contents are not saved.
+        fs::write(&path, "a").unwrap();
+        save(&path, "a").unwrap();
+        assert!(list(&path).unwrap().is_empty());
+
+        save(&path, "b").unwrap();
+        fs::write(&path, "b").unwrap();
+        let history = list(&path).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(fs::read_to_string(&history[0]).unwrap(), "a");
+
+        let timestamp = timestamp(&path, &history[0]).unwrap();
+        assert_eq!(rollback(&path, 1).unwrap(), timestamp);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a");
+        let history = list(&path).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(fs::read_to_string(&history[0]).unwrap(), "b");
+
+        assert_eq!(
+            rollback(&path, 2).unwrap_err().to_string(),
+            "there is no lock file 2 versions ago"
+        );
+    }
+
+    #[test]
+    fn history_list_is_pruned_and_sorted() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let path = temp.path().join("plugins.lock");
+        let dir = history_dir(&path);
+        fs::create_dir_all(&dir).unwrap();
+        for timestamp in 1..=HISTORY_SIZE + 5 {
+            fs::write(dir.join(format!("plugins.lock.{timestamp}")), "").unwrap();
+        }
+        fs::write(dir.join("plugins.other.lock.1"), "").unwrap();
+
+        fs::write(&path, "a").unwrap();
+        save(&path, "b").unwrap();
+
+        let history = list(&path).unwrap();
+        assert_eq!(history.len(), HISTORY_SIZE);
+        assert_eq!(fs::read_to_string(&history[0]).unwrap(), "a");
+        assert_eq!(
+            history[1],
+            dir.join(format!("plugins.lock.{}", HISTORY_SIZE + 5))
+        );
+        assert!(dir.join("plugins.other.lock.1").exists());
+    }
+}
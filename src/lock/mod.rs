@@ -1,21 +1,30 @@
 mod file;
+mod history;
 mod plugin;
+mod schema;
 mod script;
 pub mod source;
 
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
 
-use anyhow::{Context as ResultExt, Result};
+use anyhow::{bail, Context as ResultExt, Result};
 use indexmap::{indexmap, IndexMap};
 use itertools::{Either, Itertools};
 use once_cell::sync::Lazy;
 use rayon::prelude::*;
+use serde::Serialize;
+use thiserror::Error;
 
-use crate::config::{Config, MatchesProfile, Plugin, Shell};
+use crate::config::{Config, ExternalPlugin, MatchesProfile, Plugin, Shell, Source};
 use crate::context::Context;
 pub use crate::lock::file::LockedConfig;
 use crate::lock::file::{LockedExternalPlugin, LockedPlugin};
+pub use crate::lock::history::rollback;
+pub use crate::lock::schema::NewerSchemaError;
 
 /// Behaviour when locking a config file.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -26,19 +35,58 @@ pub enum LockMode {
     Update,
     /// Apply any changed configuration and reinstall all plugins.
     Reinstall,
+    /// Verify the installed plugins without installing or updating anything.
+    Frozen,
+}
+
+/// The plugins in a [`Config`] that are behind upstream.
+#[derive(Debug)]
+pub struct OutdatedConfig {
+    /// Each plugin with a Git source and how far behind upstream it is.
+    pub plugins: Vec<(String, source::Outdated)>,
+    /// Any errors that occurred while checking the sources.
+    pub errors: Vec<anyhow::Error>,
+}
+
+/// How to print the commits that changed in updated Git sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// A human readable list of commits per plugin.
+    Text,
+    /// A JSON array of updated sources.
+    Json,
+}
+
+/// A Git source that was checked out at a different commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Update {
+    /// The plugins that use this source.
+    pub plugins: Vec<String>,
+    /// The commits that changed.
+    #[serde(flatten)]
+    pub log: source::GitLog,
 }
 
 /// Read a [`LockedConfig`] from the given path.
-pub fn from_path<P>(path: P) -> Result<LockedConfig>
+///
+/// Lock files written with an older schema are migrated to the current one.
+/// Paths in the lock file are resolved relative to the current home directory.
+pub fn from_path<P>(ctx: &Context, path: P) -> Result<LockedConfig>
 where
     P: AsRef<Path>,
 {
     let path = path.as_ref();
-    let locked: LockedConfig = toml::from_str(&String::from_utf8_lossy(
+    let mut value: toml::Value = toml::from_str(&String::from_utf8_lossy(
         &fs::read(path)
             .with_context(|| format!("failed to read locked config from `{}`", path.display()))?,
     ))
     .context("failed to deserialize locked config")?;
+    schema::migrate(&mut value)
+        .with_context(|| format!("failed to migrate locked config `{}`", path.display()))?;
+    let mut locked: LockedConfig = value
+        .try_into()
+        .context("failed to deserialize locked config")?;
+    locked.resolve_paths(&ctx.home);
     Ok(locked)
 }
 
@@ -53,9 +101,15 @@ pub fn config(ctx: &Context, config: Config) -> Result<LockedConfig> {
         matches,
         apply,
         templates,
+        network,
         plugins,
     } = config;
 
+    let ctx = &Context {
+        network,
+        ..ctx.clone()
+    };
+
     let templates = {
         let mut map = shell.default_templates().clone();
         for (name, template) in templates {
@@ -64,6 +118,8 @@ pub fn config(ctx: &Context, config: Config) -> Result<LockedConfig> {
         map
     };
 
+    let multi_ref = source::multi_ref_repos(&plugins);
+
     // Partition the plugins into external and inline plugins.
     let (externals, inlines): (Vec<_>, Vec<_>) =
         plugins
@@ -78,20 +134,44 @@ pub fn config(ctx: &Context, config: Config) -> Result<LockedConfig> {
         .filter(|(_, p)| p.matches_profile(ctx))
         .map(|(i, p)| (i, LockedPlugin::Inline(p)));
 
+    // Check that the plugins to update actually exist.
+    for name in &ctx.update_plugins {
+        if !externals.iter().any(|(_, p)| &p.name == name) {
+            bail!("no plugin named `{name}` with an installable source in the config file");
+        }
+    }
+
     // Create a map of unique `Source` to `Vec<Plugin>`
     let mut map = IndexMap::new();
     for (index, plugin) in externals {
-        map.entry(plugin.source.clone())
+        map.entry(source::group_key(&plugin.source))
             .or_insert_with(|| Vec::with_capacity(1))
             .push((index, plugin));
     }
 
+    // The plugins in the existing lock file, so that Git sources are checked out
+    // at the same commits and changes to remote sources can be detected.
+    // A lock file written by a newer version of Sheldon is not overwritten.
+    let previous = match from_path(ctx, ctx.lock_file()) {
+        Ok(locked) => locked.plugins,
+        Err(err) if err.is::<NewerSchemaError>() => return Err(err),
+        Err(_) => Vec::new(),
+    };
+    let previous: IndexMap<_, _> = previous
+        .into_iter()
+        .filter_map(|plugin| match plugin {
+            LockedPlugin::External(plugin) => Some((plugin.name.clone(), plugin)),
+            LockedPlugin::Inline(_) => None,
+        })
+        .collect();
+
     let matches = matches
         .as_deref()
         .unwrap_or_else(|| shell.default_matches());
     let apply = apply.as_ref().unwrap_or_else(|| Shell::default_apply());
     let count = map.len();
     let mut errors = Vec::new();
+    let updates = Mutex::new(Vec::new());
 
     let plugins = if count == 0 {
         inlines
@@ -112,9 +192,27 @@ pub fn config(ctx: &Context, config: Config) -> Result<LockedConfig> {
                     ctx.log_status("Skipped", &source_name);
                     Ok(vec![])
                 } else {
-                    let source = source::lock(ctx, source)
+                    let mode = lock_mode(ctx, &plugins);
+                    let previous = plugins.iter().find_map(|(_, p)| previous.get(&p.name));
+                    let mut source = source::group_source(
+                        &plugins[0].1.source,
+                        plugins[1..].iter().map(|(_, p)| &p.source),
+                    );
+                    if let Source::Git { options, .. } = &mut source {
+                        options.sparse = plugin::sparse_paths(ctx, plugins.iter().map(|(_, p)| p))?;
+                    }
+                    let source = source::lock(ctx, mode, source, &multi_ref, previous)
                         .with_context(|| format!("failed to install source `{source_name}`"))?;
 
+                    if let Some(log) = source.log.clone() {
+                        let index = plugins[0].0;
+                        let plugins = plugins.iter().map(|(_, p)| p.name.clone()).collect();
+                        updates
+                            .lock()
+                            .unwrap()
+                            .push((index, Update { plugins, log }));
+                    }
+
                     let mut locked = Vec::with_capacity(plugins.len());
                     for (index, plugin) in plugins {
                         let name = plugin.name.clone();
@@ -161,14 +259,125 @@ pub fn config(ctx: &Context, config: Config) -> Result<LockedConfig> {
             .collect::<Vec<_>>()
     };
 
+    let updates = updates
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .sorted_by_key(|(index, _)| *index)
+        .map(|(_, update)| update)
+        .collect();
+
     Ok(LockedConfig {
         ctx: ctx.clone(),
         templates,
         errors,
+        updates,
         plugins,
     })
 }
 
+/// Returns the lock mode for a source used by the given plugins.
+///
+/// When updating only specific plugins, the sources of all other plugins are
+/// left as they are.
+fn lock_mode(ctx: &Context, plugins: &[(usize, ExternalPlugin)]) -> LockMode {
+    match ctx.lock_mode() {
+        LockMode::Update
+            if !ctx.update_plugins.is_empty()
+                && !plugins
+                    .iter()
+                    .any(|(_, p)| ctx.update_plugins.contains(&p.name)) =>
+        {
+            LockMode::Normal
+        }
+        mode => mode,
+    }
+}
+
+/// Check which plugins in the [`Config`] are behind upstream.
+///
+/// This fetches all Git sources in parallel but does not check anything out.
+pub fn outdated(ctx: &Context, config: Config) -> OutdatedConfig {
+    let ctx = &Context {
+        network: config.network,
+        ..ctx.clone()
+    };
+    let multi_ref = source::multi_ref_repos(&config.plugins);
+
+    // Create a map of unique `Source` to plugins
+    let mut map = IndexMap::new();
+    for plugin in config.plugins {
+        if let Plugin::External(plugin) = plugin {
+            if (&plugin).matches_profile(ctx) {
+                map.entry(source::group_key(&plugin.source))
+                    .or_insert_with(|| Vec::with_capacity(1))
+                    .push(plugin);
+            }
+        }
+    }
+
+    let mut errors = Vec::new();
+    let plugins = map
+        .into_par_iter()
+        .map(|(_, plugins)| {
+            let source =
+                source::group_source(&plugins[0].source, plugins[1..].iter().map(|p| &p.source));
+            let names: Vec<_> = plugins.into_iter().map(|p| p.name).collect();
+            let outdated = source::outdated(ctx, &source, &multi_ref)
+                .with_context(|| format!("failed to check source `{source}`"))?;
+            Ok(outdated.map(|outdated| (names, outdated)))
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .filter_map(|result| match result {
+            Ok(ok) => ok,
+            Err(err) => {
+                errors.push(err);
+                None
+            }
+        })
+        .flat_map(|(names, outdated)| names.into_iter().map(move |name| (name, outdated.clone())))
+        .collect();
+
+    OutdatedConfig { plugins, errors }
+}
+
+impl fmt::Display for Update {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} to {}",
+            self.plugins.join(", "),
+            &self.log.from[..7],
+            &self.log.to[..7]
+        )?;
+        if self.log.force_pushed {
+            write!(f, ", force-pushed")?;
+        }
+        writeln!(f, ")")?;
+        for commit in &self.log.commits {
+            writeln!(f, "  {} {}", &commit.id[..7], commit.summary)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("expected `text` or `json`, got `{}`", self.0)]
+pub struct ParseLogFormatError(String);
+
+impl FromStr for LogFormat {
+    type Err = ParseLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            s => Err(ParseLogFormatError(s.to_string())),
+        }
+    }
+}
+
 impl Shell {
     /// The default files to match on for this shell.
     fn default_matches(&self) -> &[String] {
@@ -256,6 +465,85 @@ impl LockedConfig {
     }
 }
 
+impl LockedConfig {
+    /// Describe each difference between this `LockedConfig` and a newer one.
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        let mut diffs = Vec::new();
+
+        let mut diff_field = |field: &str, old: String, new: String| {
+            if old != new {
+                diffs.push(format!("`{field}` changed from `{old}` to `{new}`"));
+            }
+        };
+        let (old, new) = (&self.ctx, &other.ctx);
+        diff_field("version", old.version.clone(), new.version.clone());
+        for (field, old, new) in [
+            ("home", &old.home, &new.home),
+            ("config_dir", &old.config_dir, &new.config_dir),
+            ("data_dir", &old.data_dir, &new.data_dir),
+            ("config_file", &old.config_file, &new.config_file),
+        ] {
+            diff_field(field, old.display().to_string(), new.display().to_string());
+        }
+        diff_field(
+            "profile",
+            old.profile().unwrap_or_default().to_string(),
+            new.profile().unwrap_or_default().to_string(),
+        );
+
+        let old: IndexMap<_, _> = self.plugins.iter().map(|p| (p.name(), p)).collect();
+        let new: IndexMap<_, _> = other.plugins.iter().map(|p| (p.name(), p)).collect();
+        for (name, plugin) in &new {
+            match old.get(name) {
+                None => diffs.push(format!("plugin `{name}` was added")),
+                Some(old_plugin) if old_plugin == plugin => {}
+                Some(old_plugin) => match (old_plugin, plugin) {
+                    (LockedPlugin::External(a), LockedPlugin::External(b))
+                        if a.commit != b.commit =>
+                    {
+                        diffs.push(format!(
+                            "plugin `{name}` changed commit from `{}` to `{}`",
+                            short_commit(&a.commit),
+                            short_commit(&b.commit)
+                        ))
+                    }
+                    _ => diffs.push(format!("plugin `{name}` was changed")),
+                },
+            }
+        }
+        for name in old.keys() {
+            if !new.contains_key(name) {
+                diffs.push(format!("plugin `{name}` was removed"));
+            }
+        }
+        if diffs.is_empty() && !old.keys().eq(new.keys()) {
+            diffs.push("plugins were reordered".to_string());
+        }
+
+        for (name, template) in &other.templates {
+            match self.templates.get(name) {
+                None => diffs.push(format!("template `{name}` was added")),
+                Some(t) if t == template => {}
+                Some(_) => diffs.push(format!("template `{name}` was changed")),
+            }
+        }
+        for name in self.templates.keys() {
+            if !other.templates.contains_key(name) {
+                diffs.push(format!("template `{name}` was removed"));
+            }
+        }
+
+        diffs
+    }
+}
+
+fn short_commit(commit: &Option<String>) -> &str {
+    match commit {
+        Some(c) => &c[..7.min(c.len())],
+        None => "none",
+    }
+}
+
 fn is_context_equal(left: &Context, right: &Context) -> bool {
     left.version == right.version
         && left.home == right.home
@@ -265,6 +553,16 @@ fn is_context_equal(left: &Context, right: &Context) -> bool {
         && left.profile == right.profile
 }
 
+impl LockedPlugin {
+    /// The name of this plugin.
+    fn name(&self) -> &str {
+        match self {
+            Self::External(plugin) => &plugin.name,
+            Self::Inline(plugin) => &plugin.name,
+        }
+    }
+}
+
 impl LockedExternalPlugin {
     /// Return a reference to the plugin directory.
     fn dir(&self) -> &Path {
@@ -285,7 +583,7 @@ mod tests {
 
     use std::io::prelude::*;
 
-    use crate::config::{ExternalPlugin, Source};
+    use crate::config::{ExternalPlugin, GitOptions, NetworkOptions, Source};
     use crate::context::Output;
     use crate::util::build;
 
@@ -306,6 +604,10 @@ mod tests {
                     no_color: true,
                 },
                 lock_mode: None,
+                update_plugins: Vec::new(),
+                update_log: None,
+                offline: false,
+                network: NetworkOptions::default(),
             }
         }
     }
@@ -320,6 +622,7 @@ mod tests {
             matches: None,
             apply: None,
             templates: IndexMap::new(),
+            network: NetworkOptions::default(),
             plugins: Vec::new(),
         };
 
@@ -334,6 +637,81 @@ mod tests {
         assert_eq!(locked.errors.len(), 0);
     }
 
+    #[test]
+    fn lock_config_git_options_merged() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let commit = |repo: &git2::Repository, builder: git2::TreeBuilder<'_>| {
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            repo.commit(Some("HEAD"), &sig, &sig, "Initial commit", &tree, &[])
+                .unwrap()
+        };
+
+        // A repository with a plugin and a submodule at `a` and `b`.
+        let upstream = temp.path().join("upstream.git");
+        let repo = git2::Repository::init_bare(&upstream).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        let mut gitmodules = String::new();
+        for name in ["a", "b"] {
+            let path = temp.path().join(format!("{name}.git"));
+            let submodule = git2::Repository::init_bare(&path).unwrap();
+            let mut tree = submodule.treebuilder(None).unwrap();
+            let blob = submodule.blob(b"echo submodule\n").unwrap();
+            tree.insert("test.plugin.zsh", blob, 0o100644).unwrap();
+            builder
+                .insert(name, commit(&submodule, tree), 0o160000)
+                .unwrap();
+            let url = Url::from_file_path(&path).unwrap();
+            gitmodules.push_str(&format!(
+                "[submodule \"{name}\"]\n\tpath = {name}\n\turl = {url}\n"
+            ));
+        }
+        let blob = repo.blob(gitmodules.as_bytes()).unwrap();
+        builder.insert(".gitmodules", blob, 0o100644).unwrap();
+        let blob = repo.blob(b"echo test\n").unwrap();
+        builder.insert("test.plugin.zsh", blob, 0o100644).unwrap();
+        commit(&repo, builder);
+
+        // Two plugins that use the same clone but need different submodules.
+        let url = Url::from_file_path(&upstream).unwrap();
+        let plugin = |name: &str| {
+            Plugin::External(ExternalPlugin {
+                name: name.to_string(),
+                source: Source::Git {
+                    url: url.clone(),
+                    reference: None,
+                    options: GitOptions {
+                        submodules: Some(vec![name.to_string()]),
+                        ..Default::default()
+                    },
+                },
+                dir: None,
+                sparse: None,
+                uses: None,
+                apply: None,
+                profiles: None,
+            })
+        };
+        let cfg = Config {
+            shell: Shell::Zsh,
+            matches: None,
+            apply: None,
+            templates: IndexMap::new(),
+            network: NetworkOptions::default(),
+            plugins: vec![plugin("a"), plugin("b")],
+        };
+
+        let locked = config(&ctx, cfg).unwrap();
+        assert!(locked.errors.is_empty());
+        let dir = ctx
+            .clone_dir()
+            .join("localhost")
+            .join(upstream.strip_prefix("/").unwrap());
+        assert!(dir.join("a/test.plugin.zsh").exists());
+        assert!(dir.join("b/test.plugin.zsh").exists());
+    }
+
     #[test]
     fn locked_config_clean() {
         let temp = tempfile::tempdir().expect("create temporary directory");
@@ -343,13 +721,16 @@ mod tests {
             matches: None,
             apply: None,
             templates: IndexMap::new(),
+            network: NetworkOptions::default(),
             plugins: vec![Plugin::External(ExternalPlugin {
                 name: "test".to_string(),
                 source: Source::Git {
                     url: Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
                     reference: None,
+                    options: GitOptions::default(),
                 },
                 dir: None,
+                sparse: None,
                 uses: None,
                 apply: None,
                 profiles: None,
@@ -381,23 +762,157 @@ mod tests {
             .exists());
     }
 
+    #[test]
+    fn update_to_string() {
+        let update = Update {
+            plugins: vec_into!["a", "b"],
+            log: source::GitLog {
+                url: Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
+                from: "ad149784a1538291f2477fb774eeeed4f4d29e45".into(),
+                to: "be8fde277e76f35efbe46848fb352cee68549962".into(),
+                force_pushed: true,
+                commits: Vec::new(),
+            },
+        };
+        assert_eq!(
+            update.to_string(),
+            "a, b (ad14978 to be8fde2, force-pushed)\n"
+        );
+    }
+
+    #[test]
+    fn locked_config_diff() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let external = |name: &str, commit: &str| {
+            LockedPlugin::External(LockedExternalPlugin {
+                name: name.to_string(),
+                source_dir: temp.path().join(name),
+                commit: Some(commit.to_string()),
+                tag: None,
+                sha256: None,
+                plugin_dir: None,
+                files: Vec::new(),
+                apply: vec_into!["source"],
+            })
+        };
+        let old = LockedConfig {
+            ctx: ctx.clone(),
+            plugins: vec![
+                external("a", "be8fde277e76f35efbe46848fb352cee68549962"),
+                external("b", "be8fde277e76f35efbe46848fb352cee68549962"),
+            ],
+            templates: indexmap_into! { "source" => "source {{ file }}" },
+            errors: Vec::new(),
+            updates: Vec::new(),
+        };
+        let new = LockedConfig {
+            ctx: Context {
+                profile: None,
+                ..ctx
+            },
+            plugins: vec![
+                external("a", "ad149784a1538291f2477fb774eeeed4f4d29e45"),
+                external("c", "be8fde277e76f35efbe46848fb352cee68549962"),
+            ],
+            templates: indexmap_into! { "PATH" => "export PATH={{ dir }}" },
+            errors: Vec::new(),
+            updates: Vec::new(),
+        };
+
+        assert_eq!(old.diff(&old), Vec::<String>::new());
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                "`profile` changed from `profile` to ``",
+                "plugin `a` changed commit from `be8fde2` to `ad14978`",
+                "plugin `c` was added",
+                "plugin `b` was removed",
+                "template `PATH` was added",
+                "template `source` was removed",
+            ]
+        );
+    }
+
     #[test]
     fn locked_config_to_and_from_path() {
         let mut temp = tempfile::NamedTempFile::new().unwrap();
-        let content = r#"version = "<version>"
-home = "<home>"
-config_dir = "<config>"
-data_dir = "<data>"
-config_file = "<config>/plugins.toml"
-plugins = []
+        let content = r#"schema = 1
+version = "<version>"
+home = "~"
+config_dir = "~/.config/sheldon"
+data_dir = "~/.local/share/sheldon"
+config_file = "~/.config/sheldon/plugins.toml"
+
+[[plugins]]
+name = "test"
+source_dir = "repos/github.com/rossmacarthur/sheldon-test"
+files = ["repos/github.com/rossmacarthur/sheldon-test/test.plugin.zsh"]
+apply = ["source"]
+
+[[plugins]]
+name = "local"
+source_dir = "~/plugins/local"
+files = ["/opt/local.zsh"]
+apply = ["source"]
 
 [templates]
 "#;
         temp.write_all(content.as_bytes()).unwrap();
-        let locked_config = from_path(temp.into_temp_path()).unwrap();
+        let ctx = Context {
+            home: "/home/test".into(),
+            ..Default::default()
+        };
+        let locked_config = from_path(&ctx, temp.into_temp_path()).unwrap();
+        assert_eq!(locked_config.ctx.home, Path::new("/home/test"));
+        assert_eq!(
+            locked_config.ctx.data_dir,
+            Path::new("/home/test/.local/share/sheldon")
+        );
+        assert_eq!(
+            locked_config.ctx.config_file,
+            Path::new("/home/test/.config/sheldon/plugins.toml")
+        );
+        match &locked_config.plugins[..] {
+            [LockedPlugin::External(test), LockedPlugin::External(local)] => {
+                assert_eq!(
+                    test.source_dir,
+                    Path::new("/home/test/.local/share/sheldon/repos/github.com/rossmacarthur/sheldon-test")
+                );
+                assert_eq!(local.source_dir, Path::new("/home/test/plugins/local"));
+                assert_eq!(local.files, [Path::new("/opt/local.zsh")]);
+            }
+            plugins => panic!("unexpected plugins {:?}", plugins),
+        }
         let temp = tempfile::NamedTempFile::new().unwrap();
         let path = temp.into_temp_path();
         locked_config.to_path(&path).unwrap();
         assert_eq!(fs::read_to_string(&path).unwrap(), content);
     }
+
+    #[test]
+    fn locked_config_from_path_absolute() {
+        let mut temp = tempfile::NamedTempFile::new().unwrap();
+        let content = r#"version = "<version>"
+home = "/home/other"
+config_dir = "/home/other/.config/sheldon"
+data_dir = "/home/other/.local/share/sheldon"
+config_file = "/home/other/.config/sheldon/plugins.toml"
+plugins = []
+
+[templates]
+"#;
+        temp.write_all(content.as_bytes()).unwrap();
+        let ctx = Context {
+            home: "/home/test".into(),
+            ..Default::default()
+        };
+        let locked_config = from_path(&ctx, temp.into_temp_path()).unwrap();
+        assert_eq!(locked_config.ctx.home, Path::new("/home/other"));
+        assert_eq!(
+            locked_config.ctx.data_dir,
+            Path::new("/home/other/.local/share/sheldon")
+        );
+        assert!(!locked_config.verify(&ctx));
+    }
 }
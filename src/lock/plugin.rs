@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context as ResultExt, Result};
@@ -22,73 +23,123 @@ pub fn lock(
         name,
         source,
         dir,
+        sparse: _,
         uses,
         apply,
         profiles: _,
     } = plugin;
 
-    let apply = apply.unwrap_or_else(|| global_apply.to_vec());
+    // GitHub releases usually contain executables, so by default the release
+    // directory is added to the `PATH`.
+    let apply = apply.unwrap_or_else(|| match source {
+        Source::GitHubRelease { .. } => vec!["PATH".to_string()],
+        _ => global_apply.to_vec(),
+    });
 
-    Ok(if let Source::Remote { .. } = source {
-        let LockedSource { dir, file } = locked_source;
-        LockedExternalPlugin {
-            name,
-            source_dir: dir,
-            plugin_dir: None,
-            files: vec![file.unwrap()],
-            apply,
-        }
-    } else {
-        // Data to use in template rendering
-        let mut data = hashmap! {
-            "data_dir" => ctx
-                .data_dir()
-                .to_str()
-                .context("data directory is not valid UTF-8")?,
-            "name" => &name
-        };
+    let commit = locked_source.commit.map(|oid| oid.to_string());
+    let tag = locked_source.tag.clone();
+    let sha256 = locked_source.sha256.clone();
 
-        let source_dir = locked_source.dir;
-        let plugin_dir = if let Some(dir) = dir {
-            let rendered = render_template(&dir, &data)?;
-            Some(source_dir.join(rendered))
-        } else {
-            None
-        };
-        let dir = plugin_dir.as_ref().unwrap_or(&source_dir);
-        let dir_as_str = dir
-            .to_str()
-            .context("plugin directory is not valid UTF-8")?;
-        data.insert("dir", dir_as_str);
-
-        let mut files = Vec::new();
-
-        // If the plugin defined what files to use, we do all of them.
-        if let Some(uses) = &uses {
-            let patterns = uses
-                .iter()
-                .map(|u| render_template(u, &data))
-                .collect::<Result<Vec<_>>>()?;
-            if !match_globs(dir, &patterns, &mut files)? {
-                bail!("failed to find any files matching any of `{:?}`", patterns);
+    Ok(
+        if let Source::Remote { .. } | Source::Command { .. } = source {
+            let LockedSource { dir, file, .. } = locked_source;
+            LockedExternalPlugin {
+                name,
+                source_dir: dir,
+                commit,
+                tag,
+                sha256,
+                plugin_dir: None,
+                files: vec![file.unwrap()],
+                apply,
             }
-        // Otherwise we try to figure out which files to use...
         } else {
-            for g in global_matches {
-                let pattern = render_template(g, &data)?;
-                if match_globs(dir, &[pattern], &mut files)? {
-                    break;
+            let mut data = template_data(ctx, &name)?;
+
+            let source_dir = locked_source.dir;
+            let plugin_dir = if let Some(dir) = dir {
+                let rendered = render_template(&dir, &data)?;
+                Some(source_dir.join(rendered))
+            } else {
+                None
+            };
+            let dir = plugin_dir.as_ref().unwrap_or(&source_dir);
+            let dir_as_str = dir
+                .to_str()
+                .context("plugin directory is not valid UTF-8")?;
+            data.insert("dir", dir_as_str);
+
+            let mut files = Vec::new();
+
+            // If the plugin defined what files to use, we do all of them.
+            if let Some(uses) = &uses {
+                let patterns = uses
+                    .iter()
+                    .map(|u| render_template(u, &data))
+                    .collect::<Result<Vec<_>>>()?;
+                if !match_globs(dir, &patterns, &mut files)? {
+                    bail!("failed to find any files matching any of `{:?}`", patterns);
+                }
+            // Otherwise we try to figure out which files to use...
+            } else {
+                for g in global_matches {
+                    let pattern = render_template(g, &data)?;
+                    if match_globs(dir, &[pattern], &mut files)? {
+                        break;
+                    }
                 }
             }
-        }
 
-        LockedExternalPlugin {
-            name,
-            source_dir,
-            plugin_dir,
-            files,
-            apply,
+            LockedExternalPlugin {
+                name,
+                source_dir,
+                commit,
+                tag,
+                sha256,
+                plugin_dir,
+                files,
+                apply,
+            }
+        },
+    )
+}
+
+/// Returns the paths to check out for a Git source used by the given plugins.
+///
+/// Each plugin needs its `dir` and any other `sparse` paths. If a plugin sets
+/// neither then it needs the whole repository and `None` is returned.
+pub fn sparse_paths<'a, I>(ctx: &Context, plugins: I) -> Result<Option<Vec<String>>>
+where
+    I: IntoIterator<Item = &'a ExternalPlugin>,
+{
+    let mut paths = Vec::new();
+    for plugin in plugins {
+        if plugin.dir.is_none() && plugin.sparse.is_none() {
+            return Ok(None);
+        }
+        let data = template_data(ctx, &plugin.name)?;
+        for path in plugin.dir.iter().chain(plugin.sparse.iter().flatten()) {
+            let rendered = render_template(path, &data)?;
+            let rendered = rendered.trim_matches('/');
+            if rendered.is_empty() {
+                return Ok(None);
+            }
+            if !paths.iter().any(|p| p == rendered) {
+                paths.push(rendered.to_string());
+            }
         }
+    }
+    Ok(Some(paths))
+}
+
+/// Data to use in template rendering.
+fn template_data<'a>(ctx: &'a Context, name: &'a str) -> Result<HashMap<&'static str, &'a str>> {
+    Ok(hashmap! {
+        "data_dir" => ctx
+            .data_dir()
+            .to_str()
+            .context("data directory is not valid UTF-8")?,
+        "name" => name
     })
 }
 
@@ -139,10 +190,13 @@ fn match_globs(dir: &Path, patterns: &[String], files: &mut Vec<PathBuf>) -> Res
 mod tests {
     use super::*;
 
+    use std::collections::HashSet;
+
     use url::Url;
 
-    use crate::config::GitReference;
+    use crate::config::{GitOptions, GitReference};
     use crate::lock::source;
+    use crate::lock::LockMode;
 
     #[test]
     fn external_plugin_lock_git_with_uses() {
@@ -154,13 +208,22 @@ mod tests {
             source: Source::Git {
                 url: Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
                 reference: Some(GitReference::Tag("v0.1.0".to_string())),
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: Some(vec!["*.md".into(), "{{ name }}.plugin.zsh".into()]),
             apply: None,
             profiles: None,
         };
-        let locked_source = source::lock(&ctx, plugin.source.clone()).unwrap();
+        let locked_source = source::lock(
+            &ctx,
+            LockMode::Normal,
+            plugin.source.clone(),
+            &HashSet::new(),
+            None,
+        )
+        .unwrap();
         let clone_dir = dir.join("repos/github.com/rossmacarthur/sheldon-test");
 
         let locked = lock(&ctx, locked_source, &[], &["hello".into()], plugin).unwrap();
@@ -187,13 +250,22 @@ mod tests {
             source: Source::Git {
                 url: Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
                 reference: Some(GitReference::Tag("v0.1.0".to_string())),
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
         };
-        let locked_source = source::lock(&ctx, plugin.source.clone()).unwrap();
+        let locked_source = source::lock(
+            &ctx,
+            LockMode::Normal,
+            plugin.source.clone(),
+            &HashSet::new(),
+            None,
+        )
+        .unwrap();
         let clone_dir = dir.join("repos/github.com/rossmacarthur/sheldon-test");
 
         let locked = lock(
@@ -221,13 +293,22 @@ mod tests {
             source: Source::Git {
                 url: Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
                 reference: Some(GitReference::Tag("v0.1.0".to_string())),
+                options: GitOptions::default(),
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
         };
-        let locked_source = source::lock(&ctx, plugin.source.clone()).unwrap();
+        let locked_source = source::lock(
+            &ctx,
+            LockMode::Normal,
+            plugin.source.clone(),
+            &HashSet::new(),
+            None,
+        )
+        .unwrap();
         let clone_dir = dir.join("repos/github.com/rossmacarthur/sheldon-test");
 
         let locked = lock(
@@ -257,13 +338,23 @@ mod tests {
                     "https://github.com/rossmacarthur/sheldon-test/raw/master/test.plugin.zsh",
                 )
                 .unwrap(),
+                mirror: None,
+                sha256: None,
             },
             dir: None,
+            sparse: None,
             uses: None,
             apply: None,
             profiles: None,
         };
-        let locked_source = source::lock(&ctx, plugin.source.clone()).unwrap();
+        let locked_source = source::lock(
+            &ctx,
+            LockMode::Normal,
+            plugin.source.clone(),
+            &HashSet::new(),
+            None,
+        )
+        .unwrap();
         let download_dir = dir.join("downloads/github.com/rossmacarthur/sheldon-test/raw/master");
 
         let locked = lock(&ctx, locked_source, &[], &["hello".to_string()], plugin).unwrap();
@@ -273,4 +364,32 @@ mod tests {
         assert_eq!(locked.files, vec![download_dir.join("test.plugin.zsh")]);
         assert_eq!(locked.apply, vec![String::from("hello")]);
     }
+
+    #[test]
+    fn sparse_paths_union() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let plugin = |name: &str, dir: Option<&str>, sparse: Option<Vec<String>>| ExternalPlugin {
+            name: name.to_string(),
+            source: Source::Git {
+                url: Url::parse("https://github.com/ohmyzsh/ohmyzsh").unwrap(),
+                reference: None,
+                options: GitOptions::default(),
+            },
+            dir: dir.map(str::to_string),
+            sparse,
+            uses: None,
+            apply: None,
+            profiles: None,
+        };
+        let git = plugin("git", Some("plugins/{{ name }}"), Some(vec_into!["lib/"]));
+        let lib = plugin("lib", Some("lib"), None);
+        let all = plugin("all", None, None);
+
+        assert_eq!(
+            sparse_paths(&ctx, [&git, &lib]).unwrap(),
+            Some(vec_into!["plugins/git", "lib"])
+        );
+        assert_eq!(sparse_paths(&ctx, [&git, &lib, &all]).unwrap(), None);
+    }
 }
@@ -0,0 +1,104 @@
+//! Lock file schema versions.
+//!
+//! The lock file records the version of its schema in the `schema` field. Lock
+//! files written with an older schema are migrated in memory when they are
+//! read, and written back with the current schema the next time the lock file
+//! changes.
+
+use std::convert::TryFrom;
+
+use anyhow::{Context as ResultExt, Result};
+use thiserror::Error;
+use toml::value::Table;
+
+/// The current lock file schema version.
+pub const SCHEMA: u32 = 1;
+
+/// The migrations from each previous schema version to the next one, indexed
+/// by the version they migrate from.
+const MIGRATIONS: [fn(&mut Table); SCHEMA as usize] = [migrate_v0];
+
+/// The error returned when a lock file was written by a newer version of
+/// Sheldon.
+#[derive(Debug, Error)]
+#[error(
+    "lock file schema version {0} is newer than the supported version {}, upgrade Sheldon or \
+     remove the lock file",
+    SCHEMA
+)]
+pub struct NewerSchemaError(pub u32);
+
+/// Migrate a raw lock file to the current schema version.
+pub fn migrate(value: &mut toml::Value) -> Result<()> {
+    let table = value
+        .as_table_mut()
+        .context("expected the lock file to be a table")?;
+    let schema = match table.get("schema") {
+        // Lock files written before the schema version was introduced.
+        None => 0,
+        Some(schema) => schema
+            .as_integer()
+            .and_then(|n| u32::try_from(n).ok())
+            .context("expected `schema` to be a non-negative integer")?,
+    };
+    if schema > SCHEMA {
+        return Err(NewerSchemaError(schema).into());
+    }
+    for migration in &MIGRATIONS[schema as usize..] {
+        migration(table);
+    }
+    table.insert("schema".into(), toml::Value::Integer(SCHEMA.into()));
+    Ok(())
+}
+
+/// Lock files without a schema version have the same fields as version 1, the
+/// only difference is that all paths are absolute, and those are still valid.
+fn migrate_v0(_: &mut Table) {}
+
+////////////////////////////////////////////////////////////////////////////////
+// Unit tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_without_schema() {
+        let mut value: toml::Value = toml::from_str("version = \"0.7.0\"").unwrap();
+        migrate(&mut value).unwrap();
+        assert_eq!(value["schema"].as_integer(), Some(SCHEMA.into()));
+        assert_eq!(value["version"].as_str(), Some("0.7.0"));
+    }
+
+    #[test]
+    fn migrate_current_schema() {
+        let mut value: toml::Value = toml::from_str(&format!("schema = {SCHEMA}")).unwrap();
+        migrate(&mut value).unwrap();
+        assert_eq!(value["schema"].as_integer(), Some(SCHEMA.into()));
+    }
+
+    #[test]
+    fn migrate_newer_schema() {
+        let mut value: toml::Value = toml::from_str(&format!("schema = {}", SCHEMA + 1)).unwrap();
+        let err = migrate(&mut value).unwrap_err();
+        assert!(err.is::<NewerSchemaError>());
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "lock file schema version {} is newer than the supported version {SCHEMA}, \
+                 upgrade Sheldon or remove the lock file",
+                SCHEMA + 1
+            )
+        );
+    }
+
+    #[test]
+    fn migrate_invalid_schema() {
+        let mut value: toml::Value = toml::from_str("schema = -1").unwrap();
+        assert_eq!(
+            migrate(&mut value).unwrap_err().to_string(),
+            "expected `schema` to be a non-negative integer"
+        );
+    }
+}
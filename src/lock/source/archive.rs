@@ -0,0 +1,698 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context as ResultExt, Result};
+use flate2::read::GzDecoder;
+use url::Url;
+use xz2::read::XzDecoder;
+
+use crate::context::Context;
+use crate::lock::source::remote;
+use crate::lock::source::{check_online, LockedSource};
+use crate::lock::LockMode;
+use crate::util::TempPath;
+
+/// A supported archive format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    TarGz,
+    TarXz,
+    Zip,
+}
+
+/// The file extensions for each archive format.
+const EXTENSIONS: &[(&str, Format)] = &[
+    (".tar.gz", Format::TarGz),
+    (".tgz", Format::TarGz),
+    (".tar.xz", Format::TarXz),
+    (".txz", Format::TarXz),
+    (".zip", Format::Zip),
+];
+
+/// Returns the directory to extract an archive into, this is the path of the
+/// downloaded archive without the extension.
+pub fn extract_dir(file: &Path) -> Result<PathBuf> {
+    let (name, _) = split_extension(file)?;
+    Ok(file.with_file_name(name))
+}
+
+/// Whether the given file name has a supported archive extension.
+pub fn is_archive(name: &str) -> bool {
+    split_extension(Path::new(name)).is_ok()
+}
+
+/// Extract an archive into the given directory, the format is determined by
+/// the file extension.
+pub fn extract_file(file: &Path, dir: &Path, strip_components: usize) -> Result<()> {
+    let (_, format) = split_extension(file)?;
+    extract(format, file, dir, strip_components)
+        .with_context(|| format!("failed to extract `{}`", file.display()))
+}
+
+/// Download and extract a remote archive.
+///
+/// The archive is extracted into `dir`, with `strip_components` leading path
+/// components removed from each entry. A downloaded archive is extracted again
+/// if `strip_components` changed. Checksums are handled the same as for a
+/// remote file, see [`remote::lock`].
+#[allow(clippy::too_many_arguments)]
+pub fn lock(
+    ctx: &Context,
+    mode: LockMode,
+    dir: PathBuf,
+    file: PathBuf,
+    url: &Url,
+    sha256: Option<&str>,
+    strip_components: usize,
+    previous: Option<&str>,
+) -> Result<LockedSource> {
+    split_extension(&file)?;
+    let is_downloaded = dir.exists() && file.exists();
+
+    if matches!(mode, LockMode::Frozen) && !is_downloaded {
+        bail!("`{}` is not downloaded", file.display());
+    }
+
+    // In offline mode an existing archive is always used.
+    let use_existing = matches!(mode, LockMode::Normal | LockMode::Frozen) || ctx.offline;
+    if use_existing && is_downloaded {
+        let checksum = remote::checksum(&file)?;
+        match sha256 {
+            Some(expected) if expected != checksum => {
+                if matches!(mode, LockMode::Frozen) {
+                    bail!(
+                        "checksum mismatch for `{}`: expected `{expected}`, got `{checksum}`",
+                        file.display()
+                    );
+                }
+            }
+            _ => {
+                if read_stamp(&file) == Some(strip_components) {
+                    ctx.log_status("Checked", url);
+                } else {
+                    extract_into(&file, &dir, strip_components)?;
+                    ctx.log_status("Extracted", url);
+                }
+                return Ok(LockedSource {
+                    dir,
+                    file: Some(file),
+                    commit: None,
+                    tag: None,
+                    sha256: Some(checksum),
+                    log: None,
+                });
+            }
+        }
+    }
+
+    check_online(ctx, url)?;
+    let download_dir = file.parent().unwrap();
+    let checksum = remote::download(ctx, download_dir, &file, url, sha256)?;
+    extract_into(&file, &dir, strip_components)?;
+    remote::log_fetched(ctx, url, &checksum, previous);
+
+    Ok(LockedSource {
+        dir,
+        file: Some(file),
+        commit: None,
+        tag: None,
+        sha256: Some(checksum),
+        log: None,
+    })
+}
+
+/// Extract an archive into a directory, replacing it if it already exists, and
+/// store the number of leading components that were stripped.
+fn extract_into(file: &Path, dir: &Path, strip_components: usize) -> Result<()> {
+    let temp_dir =
+        TempPath::new_force(dir).context("failed to prepare temporary extraction directory")?;
+    extract_file(file, temp_dir.path(), strip_components)?;
+    temp_dir
+        .rename(dir)
+        .context("failed to rename temporary extraction directory")?;
+    let path = stamp_file(file);
+    fs::write(&path, strip_components.to_string())
+        .with_context(|| format!("failed to write `{}`", path.display()))
+}
+
+/// The path that the number of leading components stripped from the entries
+/// of an extracted archive is stored at.
+pub fn stamp_file(file: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(file.file_name().unwrap());
+    name.push(".stamp");
+    file.with_file_name(name)
+}
+
+/// Read the number of leading components that were stripped from the entries
+/// of an extracted archive, if it is known.
+fn read_stamp(file: &Path) -> Option<usize> {
+    fs::read_to_string(stamp_file(file)).ok()?.parse().ok()
+}
+
+/// Split the archive extension from the file name of the given path.
+fn split_extension(file: &Path) -> Result<(&str, Format)> {
+    let name = file
+        .file_name()
+        .and_then(OsStr::to_str)
+        .with_context(|| format!("archive path `{}` is not valid UTF-8", file.display()))?;
+    EXTENSIONS
+        .iter()
+        .find_map(|(ext, format)| {
+            name.strip_suffix(ext)
+                .filter(|name| !name.is_empty())
+                .map(|name| (name, *format))
+        })
+        .with_context(|| {
+            format!(
+                "unsupported archive `{name}`, expected one of the extensions: {}",
+                EXTENSIONS
+                    .iter()
+                    .map(|(ext, _)| format!("`{ext}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+/// A symlink that was extracted from an archive.
+struct Symlink {
+    /// The path of the archive entry.
+    entry: PathBuf,
+    /// Where the symlink was extracted to.
+    link: PathBuf,
+    /// The target of the symlink.
+    target: PathBuf,
+}
+
+/// Extract an archive into the given directory.
+fn extract(format: Format, file: &Path, dir: &Path, strip_components: usize) -> Result<()> {
+    let reader = fs::File::open(file)?;
+    fs::create_dir_all(dir).with_context(|| format!("failed to create dir `{}`", dir.display()))?;
+    // Entries are checked against the canonical path, so that they can't be
+    // extracted outside of the directory through a symlink.
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("failed to canonicalize `{}`", dir.display()))?;
+    let symlinks = match format {
+        Format::TarGz => extract_tar(GzDecoder::new(reader), &dir, strip_components),
+        Format::TarXz => extract_tar(XzDecoder::new(reader), &dir, strip_components),
+        Format::Zip => extract_zip(reader, &dir, strip_components),
+    }?;
+    // A symlink can be made to point outside of the directory by another
+    // symlink that is extracted after it, so check them all again.
+    for symlink in &symlinks {
+        check_symlink(&dir, &symlink.entry, &symlink.link, &symlink.target)?;
+    }
+    Ok(())
+}
+
+fn extract_tar<R>(reader: R, dir: &Path, strip_components: usize) -> Result<Vec<Symlink>>
+where
+    R: io::Read,
+{
+    let mut archive = tar::Archive::new(reader);
+    let mut symlinks = Vec::new();
+    for entry in archive.entries().context("failed to read tar archive")? {
+        let mut entry = entry.context("failed to read tar archive entry")?;
+        let kind = entry.header().entry_type();
+        if kind.is_pax_global_extensions() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let dest = match strip(&path, strip_components)? {
+            Some(dest) => dir.join(dest),
+            None => continue,
+        };
+        create_parent_dir(dir, &path, &dest)?;
+        if kind.is_hard_link() || kind.is_symlink() {
+            let target = entry
+                .link_name()?
+                .with_context(|| format!("archive entry `{}` has no link target", path.display()))?
+                .into_owned();
+            if kind.is_symlink() {
+                check_symlink(dir, &path, &dest, &target)?;
+                symlinks.push(Symlink {
+                    entry: path.clone(),
+                    link: dest.clone(),
+                    target,
+                });
+            } else {
+                // The target of a hard link is the path of another entry, and
+                // `unpack` would resolve it relative to the current directory.
+                let src = match strip(&target, strip_components) {
+                    Ok(Some(src)) if is_inside(dir, &dir.join(&src)) => dir.join(src),
+                    _ => bail!(
+                        "archive entry `{}` links outside of the extraction directory",
+                        path.display()
+                    ),
+                };
+                fs::hard_link(&src, &dest)
+                    .with_context(|| format!("failed to unpack `{}`", path.display()))?;
+                continue;
+            }
+        }
+        entry
+            .unpack(&dest)
+            .with_context(|| format!("failed to unpack `{}`", path.display()))?;
+    }
+    Ok(symlinks)
+}
+
+fn extract_zip(reader: fs::File, dir: &Path, strip_components: usize) -> Result<Vec<Symlink>> {
+    let mut archive = zip::ZipArchive::new(reader).context("failed to read zip archive")?;
+    let mut symlinks = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .context("failed to read zip archive entry")?;
+        let path = PathBuf::from(entry.name());
+        let dest = match strip(&path, strip_components)? {
+            Some(dest) => dir.join(dest),
+            None => continue,
+        };
+        create_parent_dir(dir, &path, &dest)?;
+        if entry.is_dir() {
+            fs::create_dir_all(&dest)
+                .with_context(|| format!("failed to create dir `{}`", dest.display()))?;
+            continue;
+        }
+        // Replace an existing symlink instead of writing through it.
+        if dest.symlink_metadata().is_ok() {
+            fs::remove_file(&dest)
+                .with_context(|| format!("failed to remove `{}`", dest.display()))?;
+        }
+        let mode = entry.unix_mode();
+        if matches!(mode, Some(mode) if mode & 0o170000 == 0o120000) {
+            // The contents of a symlink entry are the target of the symlink.
+            let mut target = String::new();
+            io::Read::read_to_string(&mut entry, &mut target)
+                .with_context(|| format!("failed to unpack `{}`", path.display()))?;
+            let target = PathBuf::from(target);
+            check_symlink(dir, &path, &dest, &target)?;
+            symlink(&target, &dest)
+                .with_context(|| format!("failed to unpack `{}`", path.display()))?;
+            symlinks.push(Symlink {
+                entry: path,
+                link: dest,
+                target,
+            });
+            continue;
+        }
+        let mut file = fs::File::create(&dest)
+            .with_context(|| format!("failed to create `{}`", dest.display()))?;
+        io::copy(&mut entry, &mut file)
+            .with_context(|| format!("failed to unpack `{}`", path.display()))?;
+        if let Some(mode) = mode {
+            set_mode(&dest, mode & 0o777)
+                .with_context(|| format!("failed to set permissions on `{}`", dest.display()))?;
+        }
+    }
+    Ok(symlinks)
+}
+
+/// Strip the leading components from the path of an archive entry.
+///
+/// Returns `None` if there is nothing left after stripping, and an error if
+/// the path would be extracted outside of the extraction directory.
+fn strip(path: &Path, strip_components: usize) -> Result<Option<PathBuf>> {
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => components.push(c),
+            Component::CurDir => {}
+            _ => bail!("archive entry `{}` is not a relative path", path.display()),
+        }
+    }
+    let stripped: PathBuf = components.into_iter().skip(strip_components).collect();
+    Ok(Some(stripped).filter(|p| p.components().next().is_some()))
+}
+
+/// Create the parent directory of an archive entry's destination, unless it
+/// would be outside of the extraction directory.
+fn create_parent_dir(dir: &Path, path: &Path, dest: &Path) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        if !is_inside(dir, parent) {
+            bail!(
+                "archive entry `{}` is outside of the extraction directory",
+                path.display()
+            );
+        }
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create dir `{}`", parent.display()))?;
+    }
+    Ok(())
+}
+
+/// Check that the target of a symlink is inside of the extraction directory.
+fn check_symlink(dir: &Path, path: &Path, link: &Path, target: &Path) -> Result<()> {
+    if target.is_absolute() || !is_inside(dir, &link.parent().unwrap().join(target)) {
+        bail!(
+            "archive entry `{}` links outside of the extraction directory",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Whether a path is inside of the canonical extraction directory, once any
+/// symlinks are followed.
+///
+/// The part of the path that doesn't exist yet is resolved lexically.
+fn is_inside(dir: &Path, path: &Path) -> bool {
+    let mut rest = Vec::new();
+    let mut resolved = PathBuf::new();
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            resolved = canonical;
+            break;
+        }
+        rest.extend(ancestor.components().next_back());
+    }
+    for component in rest.into_iter().rev() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(c) => resolved.push(c),
+            _ => {}
+        }
+    }
+    resolved.starts_with(dir)
+}
+
+/// Set the Unix permissions of an extracted file.
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+/// Unix permissions are ignored on other platforms.
+#[cfg(not(unix))]
+fn set_mode(_: &Path, _: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Create a symlink extracted from a zip archive.
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Zip archives only store Unix symlinks, which can't be created on other
+/// platforms without knowing whether the target is a directory.
+#[cfg(not(unix))]
+fn symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "symlinks in zip archives are only supported on Unix",
+    ))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Unit tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    /// The files in each test archive.
+    const FILES: &[(&str, &str)] = &[
+        ("sheldon-test-0.1.0/test.plugin.zsh", "echo 'testing...'\n"),
+        ("sheldon-test-0.1.0/functions/_test", "#compdef test\n"),
+    ];
+
+    fn write_tar<W>(writer: W) -> W
+    where
+        W: io::Write,
+    {
+        let mut builder = tar::Builder::new(writer);
+        for (path, contents) in FILES {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn create_archive(path: &Path) {
+        let file = fs::File::create(path).unwrap();
+        match split_extension(path).unwrap().1 {
+            Format::TarGz => {
+                let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+                write_tar(encoder).finish().unwrap();
+            }
+            Format::TarXz => {
+                write_tar(xz2::write::XzEncoder::new(file, 6))
+                    .finish()
+                    .unwrap();
+            }
+            Format::Zip => {
+                let mut writer = zip::ZipWriter::new(file);
+                for (path, contents) in FILES {
+                    writer
+                        .start_file(*path, zip::write::FileOptions::default())
+                        .unwrap();
+                    writer.write_all(contents.as_bytes()).unwrap();
+                }
+                writer.finish().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn extract_dir_strips_extension() {
+        assert_eq!(
+            extract_dir(Path::new("/downloads/v0.1.0.tar.gz")).unwrap(),
+            Path::new("/downloads/v0.1.0")
+        );
+        assert_eq!(
+            extract_dir(Path::new("/downloads/release.zip")).unwrap(),
+            Path::new("/downloads/release")
+        );
+        assert_eq!(
+            extract_dir(Path::new("/downloads/test.plugin.zsh"))
+                .unwrap_err()
+                .to_string(),
+            "unsupported archive `test.plugin.zsh`, expected one of the extensions: `.tar.gz`, \
+             `.tgz`, `.tar.xz`, `.txz`, `.zip`"
+        );
+    }
+
+    #[test]
+    fn strip_components() {
+        let path = Path::new("./sheldon-test-0.1.0/test.plugin.zsh");
+        assert_eq!(
+            strip(path, 0).unwrap(),
+            Some(path.components().skip(1).collect())
+        );
+        assert_eq!(
+            strip(path, 1).unwrap(),
+            Some(PathBuf::from("test.plugin.zsh"))
+        );
+        assert_eq!(strip(path, 2).unwrap(), None);
+        assert!(strip(Path::new("../test.plugin.zsh"), 0).is_err());
+        assert!(strip(Path::new("/test.plugin.zsh"), 0).is_err());
+    }
+
+    #[test]
+    fn extract_formats() {
+        for name in ["test.tar.gz", "test.tar.xz", "test.zip"] {
+            let temp = tempfile::tempdir().expect("create temporary directory");
+            let file = temp.path().join(name);
+            create_archive(&file);
+
+            let dir = extract_dir(&file).unwrap();
+            let (_, format) = split_extension(&file).unwrap();
+            extract(format, &file, &dir, 1).unwrap();
+            assert_eq!(
+                fs::read_to_string(dir.join("test.plugin.zsh")).unwrap(),
+                "echo 'testing...'\n"
+            );
+            assert_eq!(
+                fs::read_to_string(dir.join("functions/_test")).unwrap(),
+                "#compdef test\n"
+            );
+        }
+    }
+
+    #[test]
+    fn extract_links() {
+        use tar::EntryType::{Link, Regular, Symlink};
+
+        /// The path and type of an entry, and the contents of a file or the
+        /// target of a link.
+        type Entry = (&'static str, tar::EntryType, &'static str);
+
+        fn create_tar_gz(path: &Path, entries: &[Entry]) {
+            let file = fs::File::create(path).unwrap();
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            let mut builder = tar::Builder::new(encoder);
+            for (path, kind, value) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(*kind);
+                header.set_mode(0o644);
+                let contents = if *kind == Regular {
+                    value.as_bytes()
+                } else {
+                    header.set_link_name_literal(value).unwrap();
+                    &[]
+                };
+                header.set_size(contents.len() as u64);
+                builder.append_data(&mut header, path, contents).unwrap();
+            }
+            builder.into_inner().unwrap().finish().unwrap();
+        }
+
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let dir = temp.path().join("extracted");
+        fs::create_dir(temp.path().join("outside")).unwrap();
+
+        // Links inside of the extraction directory are fine.
+        let file = temp.path().join("test.tar.gz");
+        create_tar_gz(
+            &file,
+            &[
+                ("pkg/test.plugin.zsh", Regular, "echo 'testing...'\n"),
+                ("pkg/functions", Symlink, "."),
+                ("pkg/symlink.zsh", Symlink, "functions/test.plugin.zsh"),
+                ("pkg/hardlink.zsh", Link, "pkg/test.plugin.zsh"),
+            ],
+        );
+        extract_file(&file, &dir, 1).unwrap();
+        for name in ["symlink.zsh", "hardlink.zsh"] {
+            assert_eq!(
+                fs::read_to_string(dir.join(name)).unwrap(),
+                "echo 'testing...'\n"
+            );
+        }
+
+        let cases: &[(&[Entry], &str)] = &[
+            (
+                &[("pkg/evil", Symlink, "../../outside")],
+                "archive entry `pkg/evil` links outside of the extraction directory",
+            ),
+            (
+                &[("pkg/evil", Symlink, "/tmp")],
+                "archive entry `pkg/evil` links outside of the extraction directory",
+            ),
+            (
+                &[("pkg/evil", Link, "/etc/passwd")],
+                "archive entry `pkg/evil` links outside of the extraction directory",
+            ),
+            // The first symlink only points outside once the second exists.
+            (
+                &[
+                    ("pkg/evil", Symlink, "a/up/../.."),
+                    ("pkg/a/up", Symlink, ".."),
+                ],
+                "archive entry `pkg/evil` links outside of the extraction directory",
+            ),
+            (
+                &[
+                    ("pkg/evil", Symlink, "a/up/.."),
+                    ("pkg/a/up", Symlink, ".."),
+                    ("pkg/evil/outside/file", Regular, "evil"),
+                ],
+                "archive entry `pkg/evil/outside/file` is outside of the extraction directory",
+            ),
+        ];
+        for (entries, expected) in cases {
+            create_tar_gz(&file, entries);
+            fs::remove_dir_all(&dir).unwrap();
+            let err = extract_file(&file, &dir, 1).unwrap_err();
+            assert_eq!(
+                format!("{err:#}"),
+                format!("failed to extract `{}`: {expected}", file.display())
+            );
+        }
+        assert!(!temp.path().join("outside/file").exists());
+
+        let file = temp.path().join("test.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&file).unwrap());
+        writer
+            .add_symlink("pkg/evil", "../../outside", Default::default())
+            .unwrap();
+        writer.finish().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let err = extract_file(&file, &dir, 1).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            format!(
+                "failed to extract `{}`: archive entry `pkg/evil` links outside of the \
+                 extraction directory",
+                file.display()
+            )
+        );
+    }
+
+    #[test]
+    fn lock_archive_from_file_url() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let root = temp.path();
+        let ctx = Context::testing(root);
+        let archive = root.join("sheldon-test-0.1.0.tar.gz");
+        create_archive(&archive);
+        let url = Url::from_file_path(&archive).unwrap();
+        let file = root.join("downloads/sheldon-test-0.1.0.tar.gz");
+        let dir = extract_dir(&file).unwrap();
+
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            file.clone(),
+            &url,
+            None,
+            1,
+            None,
+        )
+        .unwrap();
+        assert_eq!(locked.dir, dir);
+        assert_eq!(locked.file, Some(file.clone()));
+        assert_eq!(locked.sha256, Some(remote::checksum(&archive).unwrap()));
+        assert!(dir.join("test.plugin.zsh").is_file());
+
+        // The downloaded archive is extracted again if the number of stripped
+        // components changed.
+        fs::remove_file(&archive).unwrap();
+        lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            file.clone(),
+            &url,
+            None,
+            0,
+            None,
+        )
+        .unwrap();
+        assert!(!dir.join("test.plugin.zsh").exists());
+        assert!(dir.join("sheldon-test-0.1.0/test.plugin.zsh").is_file());
+        assert_eq!(read_stamp(&file), Some(0));
+
+        // A mismatched checksum is an error when frozen.
+        let err = lock(
+            &ctx,
+            LockMode::Frozen,
+            dir.clone(),
+            file.clone(),
+            &url,
+            Some(&"0".repeat(64)),
+            1,
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("checksum mismatch"));
+    }
+}
@@ -0,0 +1,315 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context as ResultExt, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::context::Context;
+use crate::lock::source::remote::{checksum, short};
+use crate::lock::source::LockedSource;
+use crate::lock::LockMode;
+use crate::util::TempPath;
+
+/// The binary that a command runs, as it was when the command was last run.
+#[derive(Debug, Deserialize, Serialize)]
+struct Stamp {
+    /// The path to the binary.
+    binary: PathBuf,
+    /// When the binary was last modified.
+    modified: Option<SystemTime>,
+    /// The SHA-256 checksum of the binary.
+    sha256: String,
+}
+
+/// Run a command and cache its output in the given file.
+///
+/// The cached output is used until the command's binary changes, or the
+/// plugins are updated or reinstalled. The `previous` checksum is the one
+/// recorded in the lock file, and is used to report when the output changed.
+pub fn lock(
+    ctx: &Context,
+    mode: LockMode,
+    dir: PathBuf,
+    file: PathBuf,
+    command: &str,
+    previous: Option<&str>,
+) -> Result<LockedSource> {
+    let locked = |sha256| LockedSource {
+        dir: dir.clone(),
+        file: Some(file.clone()),
+        commit: None,
+        tag: None,
+        sha256: Some(sha256),
+        log: None,
+    };
+
+    if matches!(mode, LockMode::Frozen) {
+        if !file.exists() {
+            bail!("the output of `{command}` is not cached");
+        }
+        ctx.log_status("Checked", &command);
+        return Ok(locked(checksum(&file)?));
+    }
+
+    if command.trim().is_empty() {
+        bail!("the command is empty");
+    }
+    let binary = find_binary(ctx, command);
+    let modified = binary
+        .as_ref()
+        .and_then(|binary| fs::metadata(binary).and_then(|m| m.modified()).ok());
+    let mut binary_checksum = None;
+
+    // Only run the command again if the binary changed. The checksum of the
+    // binary is only checked if it was modified. If the binary is unknown then
+    // the cached output is used until the plugins are updated or reinstalled.
+    if matches!(mode, LockMode::Normal) && file.exists() {
+        let binary = match &binary {
+            Some(binary) => binary,
+            None => {
+                ctx.log_status("Checked", &command);
+                return Ok(locked(checksum(&file)?));
+            }
+        };
+        if let Some(stamp) = read_stamp(&file).filter(|s| &s.binary == binary) {
+            let is_unchanged = (modified.is_some() && stamp.modified == modified) || {
+                let sha256 = checksum(binary)?;
+                let is_unchanged = sha256 == stamp.sha256;
+                binary_checksum = Some(sha256);
+                is_unchanged
+            };
+            if is_unchanged {
+                if stamp.modified != modified {
+                    write_stamp(&file, binary, modified, binary_checksum.unwrap())?;
+                }
+                ctx.log_status("Checked", &command);
+                return Ok(locked(checksum(&file)?));
+            }
+        }
+    }
+
+    let temp_file =
+        TempPath::new_force(&file).context("failed to prepare temporary output file")?;
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create dir `{}`", dir.display()))?;
+    run(command, temp_file.path())?;
+    let sha256 = checksum(temp_file.path())?;
+    temp_file
+        .rename(&file)
+        .context("failed to rename temporary output file")?;
+
+    if let Some(binary) = &binary {
+        let binary_checksum = match binary_checksum {
+            Some(sha256) => sha256,
+            None => checksum(binary)?,
+        };
+        write_stamp(&file, binary, modified, binary_checksum)?;
+    }
+
+    match previous {
+        Some(previous) if previous != sha256 => ctx.log_status(
+            "Updated",
+            &format!("{command} ({} to {})", short(previous), short(&sha256)),
+        ),
+        _ => ctx.log_status("Ran", &command),
+    }
+
+    Ok(locked(sha256))
+}
+
+/// Returns the path to the binary that a command runs.
+///
+/// This is the first word of the command after any leading `NAME=value`
+/// assignments, looked up in the `PATH` if it doesn't contain a path separator.
+/// Returns `None` if the binary can't be found, for example when the program is
+/// quoted, in which case it isn't tracked.
+fn find_binary(ctx: &Context, command: &str) -> Option<PathBuf> {
+    let program = command
+        .split_whitespace()
+        .find(|word| !is_assignment(word))?;
+    which::which(ctx.expand_tilde(PathBuf::from(program))).ok()
+}
+
+/// Whether a word of a command is a `NAME=value` variable assignment.
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+/// Run a command using `sh`, writing its output to the given file.
+fn run(command: &str, path: &Path) -> Result<()> {
+    let stdout =
+        fs::File::create(path).with_context(|| format!("failed to create `{}`", path.display()))?;
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .output()
+        .with_context(|| format!("failed to run `{command}`"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.trim() {
+            "" => bail!("`{command}` failed with {}", output.status),
+            stderr => bail!("`{command}` failed with {}: {stderr}", output.status),
+        }
+    }
+    Ok(())
+}
+
+/// The path that the stamp of a command's cached output is stored at.
+pub fn stamp_file(file: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(file.file_name().unwrap());
+    name.push(".stamp");
+    file.with_file_name(name)
+}
+
+/// Read the stamp of a command's cached output, if there is one.
+fn read_stamp(file: &Path) -> Option<Stamp> {
+    let contents = fs::read(stamp_file(file)).ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+/// Store the stamp of a command's cached output, so that the command is only
+/// run again if the binary changed.
+fn write_stamp(
+    file: &Path,
+    binary: &Path,
+    modified: Option<SystemTime>,
+    sha256: String,
+) -> Result<()> {
+    let path = stamp_file(file);
+    let stamp = Stamp {
+        binary: binary.to_path_buf(),
+        modified,
+        sha256,
+    };
+    let contents = serde_json::to_vec(&stamp)?;
+    fs::write(&path, contents).with_context(|| format!("failed to write `{}`", path.display()))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Unit tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_command_cached() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let dir = temp.path().join("commands");
+        let file = dir.join("echo");
+
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            file.clone(),
+            "echo hello",
+            None,
+        )
+        .unwrap();
+        assert_eq!(locked.file, Some(file.clone()));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "hello\n");
+        assert!(stamp_file(&file).exists());
+
+        // The cached output is used while the binary is unchanged.
+        fs::write(&file, "cached\n").unwrap();
+        lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            file.clone(),
+            "echo hello",
+            None,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "cached\n");
+
+        // Updating always runs the command again.
+        let previous = locked.sha256.as_deref();
+        lock(
+            &ctx,
+            LockMode::Update,
+            dir,
+            file.clone(),
+            "echo hello",
+            previous,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn lock_command_find_binary() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let echo = which::which("echo").unwrap();
+
+        assert_eq!(find_binary(&ctx, "echo hello"), Some(echo.clone()));
+        assert_eq!(find_binary(&ctx, "FOO=1 BAR= echo hello"), Some(echo));
+        assert_eq!(find_binary(&ctx, "\"my tool\" init"), None);
+        assert_eq!(find_binary(&ctx, "./does-not-exist init"), None);
+    }
+
+    #[test]
+    fn lock_command_untracked_binary() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let dir = temp.path().join("commands");
+        let file = dir.join("echo");
+        let command = "'echo' hello";
+
+        lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            file.clone(),
+            command,
+            None,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "hello\n");
+        assert!(!stamp_file(&file).exists());
+
+        // The cached output is used until the plugins are updated.
+        fs::write(&file, "cached\n").unwrap();
+        lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            file.clone(),
+            command,
+            None,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "cached\n");
+        lock(&ctx, LockMode::Update, dir, file.clone(), command, None).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn lock_command_failed() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let dir = temp.path().join("commands");
+        let file = dir.join("false");
+
+        let err = lock(&ctx, LockMode::Normal, dir, file.clone(), "false", None).unwrap_err();
+        assert_eq!(err.to_string(), "`false` failed with exit status: 1");
+        assert!(!file.exists());
+    }
+}
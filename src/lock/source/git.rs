@@ -1,16 +1,22 @@
 use std::fmt;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context as ResultExt, Result};
+use anyhow::{bail, Context as ResultExt, Result};
+use serde::Serialize;
 use url::Url;
 
-use crate::config::GitReference;
+use crate::config::{GitOptions, GitReference};
 use crate::context::Context;
-use crate::lock::source::LockedSource;
+use crate::lock::source::{check_online, request_options, retry, LockedSource};
 use crate::lock::LockMode;
 use crate::util::git;
 use crate::util::TempPath;
 
+/// The number of times to double the depth of a shallow clone when looking
+/// for a commit, before fetching the complete history.
+const MAX_DEEPEN_ATTEMPTS: u32 = 4;
+
 #[derive(Clone, Debug)]
 pub enum GitCheckout {
     /// Checkout the latest of the default branch (HEAD).
@@ -21,47 +27,318 @@ pub enum GitCheckout {
     Rev(String),
     /// Checkout a tag.
     Tag(String),
+    /// Checkout the highest tag matching a semantic version requirement.
+    Version(String),
+}
+
+/// How far a checked out Git repository is behind upstream.
+#[derive(Debug, Clone)]
+pub struct Outdated {
+    /// The commit that is currently checked out.
+    pub current: git2::Oid,
+    /// The commit that would be checked out when updating.
+    pub available: git2::Oid,
+    /// The number of commits that the current commit is behind.
+    pub behind: usize,
+    /// The tags that are newer than the configured tag.
+    pub tags: Vec<String>,
+}
+
+/// The commits between the previous and the new checkout of a Git repository.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GitLog {
+    /// The URL of the Git repository.
+    pub url: Url,
+    /// The commit that was previously checked out.
+    pub from: String,
+    /// The commit that is now checked out.
+    pub to: String,
+    /// Whether the previous commit is not an ancestor of the new commit, i.e.
+    /// the upstream history was rewritten.
+    pub force_pushed: bool,
+    /// The new commits, newest first.
+    pub commits: Vec<GitLogCommit>,
+}
+
+/// A single commit in a [`GitLog`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GitLogCommit {
+    /// The commit identifier.
+    pub id: String,
+    /// The first line of the commit message.
+    pub summary: String,
 }
 
 /// Clones a Git repository and checks it out at a particular revision.
-pub fn lock(ctx: &Context, dir: PathBuf, url: &Url, c: GitCheckout) -> Result<LockedSource> {
-    match ctx.lock_mode() {
-        LockMode::Normal => match git::open(&dir) {
-            Ok(repo) => {
-                if checkout(ctx, &repo, url, c.clone()).is_err() {
-                    git::fetch(&repo)?;
-                    checkout(ctx, &repo, url, c)?;
-                }
-                Ok(LockedSource { dir, file: None })
+///
+/// If a locked `commit` is given, the repository is checked out at that commit
+/// instead, see [`GitCheckout::resolve_locked`].
+pub fn lock(
+    ctx: &Context,
+    mode: LockMode,
+    dir: PathBuf,
+    url: &Url,
+    c: GitCheckout,
+    options: &GitOptions,
+    commit: Option<git2::Oid>,
+) -> Result<LockedSource> {
+    match mode {
+        LockMode::Normal => match open(ctx, &dir, url) {
+            Ok((repo, moved)) => {
+                let oid = match c.resolve_locked(&repo, commit) {
+                    // The remote refs are out of date if `origin` moved.
+                    Ok(oid) if !moved || ctx.offline => oid,
+                    Err(err) if ctx.offline => {
+                        return Err(err).with_context(|| {
+                            format!("`{url}{c}` cannot be fetched in offline mode")
+                        });
+                    }
+                    _ => {
+                        fetch(ctx, &repo, url, &c, options)?;
+                        resolve_deepening(ctx, &repo, url, &c, options, commit)?
+                    }
+                };
+                git::sparse_checkout(&repo, options.sparse.as_deref())?;
+                let log = checkout(ctx, &repo, url, &c, options, oid)?;
+                Ok(LockedSource {
+                    dir,
+                    file: None,
+                    commit: Some(oid),
+                    tag: c.chosen_tag(&repo, oid)?,
+                    sha256: None,
+                    log,
+                })
             }
-            Err(_) => install(ctx, dir, url, c),
+            Err(_) => install(ctx, dir, url, c, options, commit),
         },
-        LockMode::Update => match git::open(&dir) {
-            Ok(repo) => {
-                git::fetch(&repo)?;
-                checkout(ctx, &repo, url, c)?;
-                Ok(LockedSource { dir, file: None })
+        // Updating ignores the locked commit.
+        LockMode::Update => match open(ctx, &dir, url) {
+            Ok((repo, _)) => {
+                // In offline mode the repository is updated to whatever was
+                // last fetched.
+                let oid = if ctx.offline {
+                    c.resolve(&repo)?
+                } else {
+                    fetch(ctx, &repo, url, &c, options)?;
+                    resolve_deepening(ctx, &repo, url, &c, options, None)?
+                };
+                git::sparse_checkout(&repo, options.sparse.as_deref())?;
+                let log = checkout(ctx, &repo, url, &c, options, oid)?;
+                Ok(LockedSource {
+                    dir,
+                    file: None,
+                    commit: Some(oid),
+                    tag: c.chosen_tag(&repo, oid)?,
+                    sha256: None,
+                    log,
+                })
             }
-            Err(_) => install(ctx, dir, url, c),
+            Err(_) => install(ctx, dir, url, c, options, None),
         },
-        LockMode::Reinstall => install(ctx, dir, url, c),
+        LockMode::Reinstall => install(ctx, dir, url, c, options, commit),
+        LockMode::Frozen => {
+            let repo = git::open(&dir)?;
+            let origin = git::origin_url(&repo)?;
+            if origin != url.as_str() {
+                bail!("the `origin` remote is `{origin}`, not `{url}`");
+            }
+            let current_oid = repo.head()?.target().context("current HEAD as no target")?;
+            let expected_oid = c.resolve_locked(&repo, commit)?;
+            if current_oid != expected_oid {
+                bail!(
+                    "checked out commit `{}` does not match locked commit `{}`",
+                    &current_oid.to_string()[..7],
+                    &expected_oid.to_string()[..7]
+                );
+            }
+            ctx.log_status("Checked", &format!("{url}{c}"));
+            Ok(LockedSource {
+                dir,
+                file: None,
+                commit: Some(current_oid),
+                tag: c.chosen_tag(&repo, current_oid)?,
+                sha256: None,
+                log: None,
+            })
+        }
     }
 }
 
-/// Checks if a repository is correctly checked out, if not checks it out.
-fn checkout(
+/// Fetches a Git repository and compares the checked out commit to the one that
+/// would be checked out when updating, without checking anything out.
+pub fn outdated(
+    ctx: &Context,
+    dir: &Path,
+    url: &Url,
+    c: &GitCheckout,
+    options: &GitOptions,
+) -> Result<Outdated> {
+    let repo = git::open(dir)?;
+    if !ctx.offline {
+        if git::origin_url(&repo)? == url.as_str() {
+            fetch(ctx, &repo, url, c, options)?;
+        } else {
+            // Changing the `origin` remote is left to `lock`, so the URL is
+            // fetched directly.
+            let request = request_options(ctx, url)?;
+            retry(ctx, url, || {
+                if repo.is_shallow() {
+                    let depth = options.depth;
+                    git::fetch_shallow(&repo, url.as_str(), &c.refspecs(), depth, None, &request)
+                } else {
+                    git::fetch_url(&repo, url, &request)
+                }
+            })?;
+        }
+    }
+    let current = repo.head()?.target().context("current HEAD as no target")?;
+    let available = c.resolve(&repo)?;
+    let (_, behind) = repo.graph_ahead_behind(current, available)?;
+    let tags = match c {
+        GitCheckout::Tag(tag) => git::newer_tags(&repo, tag)?,
+        _ => Vec::new(),
+    };
+    if !ctx.offline {
+        ctx.log_status("Fetched", &format!("{url}{c}"));
+    }
+    Ok(Outdated {
+        current,
+        available,
+        behind,
+        tags,
+    })
+}
+
+/// Open an existing clone of a Git repository.
+///
+/// If the `origin` remote is not the given URL, e.g. because the protocol was
+/// changed or the plugin moved to a fork, then the remote is changed and the
+/// returned flag is set. Shallow and partial clones only contain part of the
+/// old remote's history, so an error is returned instead and they are cloned
+/// again, unless in offline mode.
+fn open(ctx: &Context, dir: &Path, url: &Url) -> Result<(git2::Repository, bool)> {
+    let repo = git::open(dir)?;
+    if !ctx.offline && (repo.is_shallow() || git::is_partial(&repo)) {
+        let origin = git::origin_url(&repo)?;
+        if origin != url.as_str() {
+            ctx.log_status("Recloning", &format!("{url} (`origin` was {origin})"));
+            bail!("the `origin` remote is `{origin}`, not `{url}`");
+        }
+    }
+    let moved = update_origin(ctx, &repo, url)?;
+    Ok((repo, moved))
+}
+
+/// Change the `origin` remote of a repository to the given URL, returns
+/// whether it was changed.
+fn update_origin(ctx: &Context, repo: &git2::Repository, url: &Url) -> Result<bool> {
+    let origin = git::origin_url(repo)?;
+    if origin == url.as_str() {
+        return Ok(false);
+    }
+    git::set_origin_url(repo, url)?;
+    ctx.log_status("Moved", &format!("`origin` from {origin} to {url}"));
+    Ok(true)
+}
+
+/// Fetch a Git repository.
+///
+/// Shallow and partial clones only fetch the refs needed for the checkout.
+fn fetch(
     ctx: &Context,
     repo: &git2::Repository,
     url: &Url,
-    checkout: GitCheckout,
+    c: &GitCheckout,
+    options: &GitOptions,
 ) -> Result<()> {
+    let request = request_options(ctx, url)?;
+    retry(ctx, url, || {
+        if options.is_shallow_or_partial() {
+            git::fetch_shallow(
+                repo,
+                "origin",
+                &c.refspecs(),
+                options.depth,
+                options.filter.as_deref(),
+                &request,
+            )
+        } else {
+            git::fetch(repo, &request)
+        }
+    })
+}
+
+/// Resolve the commit to check out, fetching more history of a shallow clone
+/// until the commit is reachable.
+///
+/// The depth is doubled each time, and after a few attempts the complete
+/// history is fetched. If the locked commit is still missing then it is no
+/// longer on the remote, e.g. because the history was rewritten, so the
+/// checkout is resolved without it.
+fn resolve_deepening(
+    ctx: &Context,
+    repo: &git2::Repository,
+    url: &Url,
+    c: &GitCheckout,
+    options: &GitOptions,
+    locked: Option<git2::Oid>,
+) -> Result<git2::Oid> {
+    let mut result = c.resolve_locked(repo, locked);
+    let mut depth = options.depth.unwrap_or(1);
+    let mut attempts = 0;
+    while result.is_err() && repo.is_shallow() {
+        let request = request_options(ctx, url)?;
+        if attempts < MAX_DEEPEN_ATTEMPTS {
+            depth = depth.saturating_mul(2);
+            retry(ctx, url, || {
+                let filter = options.filter.as_deref();
+                git::fetch_shallow(repo, "origin", &c.refspecs(), Some(depth), filter, &request)
+            })?;
+        } else {
+            retry(ctx, url, || git::unshallow(repo, &c.refspecs(), &request))?;
+        }
+        attempts += 1;
+        result = c.resolve_locked(repo, locked);
+    }
+    match (result, locked) {
+        (Err(_), Some(locked)) if repo.find_commit(locked).is_err() => {
+            ctx.log_warning(
+                "Missing",
+                &format!("{url}{c}: locked commit `{locked}` no longer exists"),
+            );
+            c.resolve(repo)
+        }
+        (result, _) => result,
+    }
+}
+
+/// Checks if a repository is checked out at the given commit, if not checks
+/// it out.
+///
+/// If the repository was moved and a log was requested, returns the commits
+/// between the two.
+fn checkout(
+    ctx: &Context,
+    repo: &git2::Repository,
+    url: &Url,
+    checkout: &GitCheckout,
+    options: &GitOptions,
+    expected_oid: git2::Oid,
+) -> Result<Option<GitLog>> {
     let current_oid = repo.head()?.target().context("current HEAD as no target")?;
-    let expected_oid = checkout.resolve(repo)?;
+    let mut log = None;
     if current_oid == expected_oid {
         ctx.log_status("Checked", &format!("{url}{checkout}"))
     } else {
+        // The history of a shallow clone is incomplete, so there is no log.
+        if ctx.update_log.is_some() && !repo.is_shallow() {
+            log = Some(
+                git_log(repo, url, current_oid, expected_oid)
+                    .context("failed to walk the updated commits")?,
+            );
+        }
         git::checkout(repo, expected_oid)?;
-        git::submodule_update(repo).context("failed to recursively update")?;
+        submodule_update(ctx, repo, url, options)?;
         ctx.log_status(
             "Updated",
             &format!(
@@ -73,29 +350,114 @@ fn checkout(
             ),
         );
     }
+    Ok(log)
+}
+
+/// Update the configured submodules of a repository, logging each submodule
+/// that was updated.
+fn submodule_update(
+    ctx: &Context,
+    repo: &git2::Repository,
+    url: &Url,
+    options: &GitOptions,
+) -> Result<()> {
+    let updated = git::submodule_update(repo, options.submodules.as_deref())
+        .context("failed to recursively update")?;
+    for path in updated {
+        ctx.log_status(
+            "Updated",
+            &format!("submodule `{}` of {url}", path.display()),
+        );
+    }
     Ok(())
 }
 
-fn install(ctx: &Context, dir: PathBuf, url: &Url, checkout: GitCheckout) -> Result<LockedSource> {
+/// Collect the commits that are reachable from `to` but not from `from`.
+fn git_log(
+    repo: &git2::Repository,
+    url: &Url,
+    from: git2::Oid,
+    to: git2::Oid,
+) -> Result<GitLog, git2::Error> {
+    let force_pushed = !repo.graph_descendant_of(to, from)?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(to)?;
+    revwalk.hide(from)?;
+    let commits = revwalk
+        .map(|oid| {
+            let commit = repo.find_commit(oid?)?;
+            Ok(GitLogCommit {
+                id: commit.id().to_string(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+            })
+        })
+        .collect::<Result<_, git2::Error>>()?;
+    Ok(GitLog {
+        url: url.clone(),
+        from: from.to_string(),
+        to: to.to_string(),
+        force_pushed,
+        commits,
+    })
+}
+
+fn install(
+    ctx: &Context,
+    dir: PathBuf,
+    url: &Url,
+    checkout: GitCheckout,
+    options: &GitOptions,
+    commit: Option<git2::Oid>,
+) -> Result<LockedSource> {
+    check_online(ctx, url)?;
     let temp_dir =
         TempPath::new_force(&dir).context("failed to prepare temporary clone directory")?;
-    {
-        let repo = git::clone(url, temp_dir.path())?;
-        git::checkout(&repo, checkout.resolve(&repo)?)?;
-        git::submodule_update(&repo).context("failed to recursively update")?;
-    } // `repo` must be dropped before renaming the directory
+    let request = request_options(ctx, url)?;
+    let (oid, tag) = {
+        let repo = retry(ctx, url, || {
+            // Start from scratch if a previous attempt failed part way through.
+            if temp_dir.path().exists() {
+                fs::remove_dir_all(temp_dir.path())
+                    .context("failed to remove temporary clone directory")?;
+            }
+            if options.is_shallow_or_partial() {
+                git::clone_shallow(
+                    url,
+                    temp_dir.path(),
+                    &checkout.refspecs(),
+                    options.depth,
+                    options.filter.as_deref(),
+                    &request,
+                )
+            } else {
+                git::clone(url, temp_dir.path(), &request)
+            }
+        })?;
+        let oid = resolve_deepening(ctx, &repo, url, &checkout, options, commit)?;
+        git::sparse_checkout(&repo, options.sparse.as_deref())?;
+        git::checkout(&repo, oid)?;
+        submodule_update(ctx, &repo, url, options)?;
+        (oid, checkout.chosen_tag(&repo, oid)?)
+    }; // `repo` must be dropped before renaming the directory
     temp_dir
         .rename(&dir)
         .context("failed to rename temporary clone directory")?;
     ctx.log_status("Cloned", &format!("{url}{checkout}"));
-    Ok(LockedSource { dir, file: None })
+    Ok(LockedSource {
+        dir,
+        file: None,
+        commit: Some(oid),
+        tag,
+        sha256: None,
+        log: None,
+    })
 }
 
 impl fmt::Display for GitCheckout {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::DefaultBranch => write!(f, ""),
-            Self::Branch(s) | Self::Rev(s) | Self::Tag(s) => write!(f, "@{s}"),
+            Self::Branch(s) | Self::Rev(s) | Self::Tag(s) | Self::Version(s) => write!(f, "@{s}"),
         }
     }
 }
@@ -107,11 +469,25 @@ impl From<Option<GitReference>> for GitCheckout {
             Some(GitReference::Branch(s)) => Self::Branch(s),
             Some(GitReference::Rev(s)) => Self::Rev(s),
             Some(GitReference::Tag(s)) => Self::Tag(s),
+            Some(GitReference::Version(s)) => Self::Version(s),
         }
     }
 }
 
 impl GitCheckout {
+    /// The refspecs to fetch for a shallow or partial clone, only the refs
+    /// that are needed for this checkout.
+    fn refspecs(&self) -> Vec<String> {
+        match self {
+            Self::DefaultBranch => vec_into!["+HEAD:refs/remotes/origin/HEAD"],
+            Self::Branch(s) => vec![format!("+refs/heads/{s}:refs/remotes/origin/{s}")],
+            Self::Tag(s) => vec![format!("+refs/tags/{s}:refs/tags/{s}")],
+            Self::Version(_) => vec_into!["+refs/tags/*:refs/tags/*"],
+            // The revision could be on any branch.
+            Self::Rev(_) => git::DEFAULT_REFSPECS.clone(),
+        }
+    }
+
     /// Resolve `GitCheckout` to a Git object identifier.
     fn resolve(&self, repo: &git2::Repository) -> Result<git2::Oid> {
         match self {
@@ -119,6 +495,58 @@ impl GitCheckout {
             Self::Branch(s) => git::resolve_branch(repo, s),
             Self::Rev(s) => git::resolve_rev(repo, s),
             Self::Tag(s) => git::resolve_tag(repo, s),
+            Self::Version(s) => match git::resolve_version(repo, s)?.into_iter().next() {
+                Some((_, oid)) => Ok(oid),
+                None => bail!("failed to find a tag matching version `{s}`"),
+            },
+        }
+    }
+
+    /// Returns the tag that was chosen for a version requirement, given the
+    /// commit that was checked out.
+    fn chosen_tag(&self, repo: &git2::Repository, oid: git2::Oid) -> Result<Option<String>> {
+        match self {
+            Self::Version(s) => Ok(git::resolve_version(repo, s)?
+                .into_iter()
+                .find(|(_, tag_oid)| *tag_oid == oid)
+                .map(|(tag, _)| tag)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Resolve `GitCheckout` to a Git object identifier, preferring the given
+    /// locked commit.
+    ///
+    /// The locked commit is only used for branches, and only if it is
+    /// reachable from the tip of the branch. Otherwise the branch has been
+    /// changed in the config or its history was rewritten, so we fall back to
+    /// the tip. It is an error if the locked commit doesn't exist, so that it
+    /// can be fetched, see [`resolve_deepening`]. For versions the locked commit is used if it is still one of
+    /// the matching tags. Tags and revisions always resolve to what is
+    /// configured.
+    fn resolve_locked(
+        &self,
+        repo: &git2::Repository,
+        locked: Option<git2::Oid>,
+    ) -> Result<git2::Oid> {
+        let oid = self.resolve(repo)?;
+        match (self, locked) {
+            (Self::DefaultBranch | Self::Branch(_), Some(locked)) if locked != oid => {
+                repo.find_commit(locked)
+                    .with_context(|| format!("failed to find locked commit `{locked}`"))?;
+                if repo.graph_descendant_of(oid, locked)? {
+                    Ok(locked)
+                } else {
+                    Ok(oid)
+                }
+            }
+            (Self::Version(s), Some(locked)) if locked != oid => {
+                let is_match = git::resolve_version(repo, s)?
+                    .into_iter()
+                    .any(|(_, tag_oid)| tag_oid == locked);
+                Ok(if is_match { locked } else { oid })
+            }
+            _ => Ok(oid),
         }
     }
 }
@@ -185,6 +613,27 @@ mod tests {
         assert_eq!(error.to_string(), "failed to find tag `v0.2.0`");
     }
 
+    #[test]
+    fn git_checkout_resolve_version() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let repo = git_clone_sheldon_test(&temp);
+
+        let checkout = GitCheckout::Version("^0.1".to_string());
+        let oid = checkout.resolve(&repo).unwrap();
+        assert_eq!(oid.to_string(), "be8fde277e76f35efbe46848fb352cee68549962");
+        assert_eq!(
+            checkout.chosen_tag(&repo, oid).unwrap(),
+            Some("v0.1.0".to_string())
+        );
+
+        let checkout = GitCheckout::Version("^0.2".to_string());
+        let error = checkout.resolve(&repo).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to find a tag matching version `^0.2`"
+        );
+    }
+
     fn git_clone_sheldon_test(temp: &tempfile::TempDir) -> git2::Repository {
         let dir = temp.path();
         Command::new("git")
@@ -196,14 +645,528 @@ mod tests {
         git2::Repository::open(dir).expect("open sheldon-test git repository")
     }
 
+    /// Create a local bare repository with the given number of commits on the
+    /// default branch, returning its URL and the commits, oldest first.
+    fn git_init_local(dir: &Path, count: usize) -> (Url, Vec<git2::Oid>) {
+        let repo = git2::Repository::init_bare(dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_bool("uploadpack.allowFilter", true).unwrap();
+        config
+            .set_bool("uploadpack.allowAnySHA1InWant", true)
+            .unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let mut commits: Vec<git2::Oid> = Vec::new();
+        for i in 0..count {
+            let blob = repo.blob(format!("echo {i}\n").as_bytes()).unwrap();
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("test.plugin.zsh", blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parents: Vec<_> = commits
+                .last()
+                .map(|oid| repo.find_commit(*oid).unwrap())
+                .into_iter()
+                .collect();
+            let parents: Vec<_> = parents.iter().collect();
+            let oid = repo
+                .commit(
+                    Some("HEAD"),
+                    &sig,
+                    &sig,
+                    &format!("Commit {i}"),
+                    &tree,
+                    &parents,
+                )
+                .unwrap();
+            commits.push(oid);
+        }
+        (Url::from_file_path(dir).unwrap(), commits)
+    }
+
+    #[test]
+    fn lock_git_shallow_and_deepen() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let (url, commits) = git_init_local(&temp.path().join("upstream.git"), 10);
+        let dir = temp.path().join("clone");
+        let options = GitOptions {
+            depth: Some(1),
+            filter: None,
+            sparse: None,
+            mirror: None,
+            submodules: None,
+        };
+
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            &url,
+            GitCheckout::DefaultBranch,
+            &options,
+            None,
+        )
+        .unwrap();
+        assert_eq!(locked.commit, Some(commits[9]));
+        let repo = git2::Repository::open(&dir).unwrap();
+        assert!(repo.is_shallow());
+        assert!(repo.find_commit(commits[8]).is_err());
+        assert_eq!(
+            fs::read_to_string(dir.join("test.plugin.zsh")).unwrap(),
+            "echo 9\n"
+        );
+
+        // A revision outside of the fetched history is found by deepening.
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            &url,
+            GitCheckout::Rev(commits[2].to_string()),
+            &options,
+            None,
+        )
+        .unwrap();
+        assert_eq!(locked.commit, Some(commits[2]));
+        assert_eq!(
+            fs::read_to_string(dir.join("test.plugin.zsh")).unwrap(),
+            "echo 2\n"
+        );
+    }
+
+    #[test]
+    fn lock_git_origin_moved() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let (url_a, _) = git_init_local(&temp.path().join("a.git"), 2);
+        let (url_b, commits_b) = git_init_local(&temp.path().join("b.git"), 3);
+
+        let shallow = GitOptions {
+            depth: Some(1),
+            ..Default::default()
+        };
+        for options in [GitOptions::default(), shallow] {
+            let dir = temp.path().join("clone");
+            let _ = fs::remove_dir_all(&dir);
+            for url in [&url_a, &url_b] {
+                lock(
+                    &ctx,
+                    LockMode::Normal,
+                    dir.clone(),
+                    url,
+                    GitCheckout::DefaultBranch,
+                    &options,
+                    None,
+                )
+                .unwrap();
+            }
+            // The clone is updated, or cloned again, from the new remote.
+            let repo = git2::Repository::open(&dir).unwrap();
+            assert_eq!(git::origin_url(&repo).unwrap(), url_b.as_str());
+            assert_eq!(repo.head().unwrap().target(), Some(commits_b[2]));
+            assert_eq!(repo.is_shallow(), options.depth.is_some());
+        }
+    }
+
+    #[test]
+    fn outdated_git_origin_moved() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let (url_a, commits_a) = git_init_local(&temp.path().join("a.git"), 2);
+        let (url_b, commits_b) = git_init_local(&temp.path().join("b.git"), 3);
+        let dir = temp.path().join("clone");
+        let options = GitOptions::default();
+        lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            &url_a,
+            GitCheckout::DefaultBranch,
+            &options,
+            None,
+        )
+        .unwrap();
+
+        // The new URL is fetched but the `origin` remote is left alone.
+        let outdated = outdated(&ctx, &dir, &url_b, &GitCheckout::DefaultBranch, &options).unwrap();
+        assert_eq!(outdated.current, commits_a[1]);
+        assert_eq!(outdated.available, commits_b[2]);
+        let repo = git2::Repository::open(&dir).unwrap();
+        assert_eq!(git::origin_url(&repo).unwrap(), url_a.as_str());
+    }
+
+    #[test]
+    fn outdated_git_newer_tags() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let (_, commits) = git_init_local(&temp.path().join("upstream.git"), 4);
+        let repo = git2::Repository::open(temp.path().join("upstream.git")).unwrap();
+        let tag = |name: &str, oid: git2::Oid| {
+            let obj = repo.find_object(oid, None).unwrap();
+            repo.tag_lightweight(name, &obj, false).unwrap();
+        };
+        tag("v0.1.0", commits[0]);
+        tag("nightly", commits[1]);
+        tag("v0.10.0", commits[1]);
+        tag("v0.2.0", commits[3]);
+        tag("v0.3.0", commits[2]);
+        // A tag that points to a tree instead of a commit.
+        let tree = repo.find_commit(commits[3]).unwrap().tree_id();
+        tag("tree", tree);
+
+        let tags = git::newer_tags(&repo, "v0.1.0").unwrap();
+        assert_eq!(tags, ["v0.2.0", "v0.3.0", "v0.10.0", "nightly"]);
+    }
+
+    #[test]
+    fn lock_git_stalled() {
+        use std::io::{Read, Write};
+
+        use crate::util::test_server::{self, Server};
+
+        fn pkt_line(data: &[u8]) -> Vec<u8> {
+            let mut pkt = format!("{:04x}", data.len() + 4).into_bytes();
+            pkt.extend(data);
+            pkt
+        }
+
+        // A smart HTTP server that advertises a commit and then stalls in the
+        // middle of sending the pack.
+        let server = Server::new(|request, stream| {
+            if request.method == "GET" {
+                let oid = "1".repeat(40);
+                let mut body = pkt_line(b"# service=git-upload-pack\n");
+                body.extend(b"0000");
+                body.extend(pkt_line(format!("{oid} HEAD\0side-band-64k\n").as_bytes()));
+                body.extend(pkt_line(format!("{oid} refs/heads/master\n").as_bytes()));
+                body.extend(b"0000");
+                let content_type = "application/x-git-upload-pack-advertisement";
+                test_server::respond(stream, "200 OK", &[("Content-Type", content_type)], &body);
+            } else {
+                let header = "HTTP/1.1 200 OK\r\n\
+                              Content-Type: application/x-git-upload-pack-result\r\n\
+                              Transfer-Encoding: chunked\r\n\r\n";
+                stream.write_all(header.as_bytes()).unwrap();
+                let mut write_chunk = |data: &[u8]| {
+                    let mut chunk = format!("{:x}\r\n", data.len()).into_bytes();
+                    chunk.extend(data);
+                    chunk.extend(b"\r\n");
+                    stream.write_all(&chunk)
+                };
+                // libgit2 reports the progress every 100 KiB, so send more
+                // than that of side-band progress messages either side of
+                // a pause that is longer than the timeout.
+                let mut progress = vec![2];
+                progress.extend(vec![b'.'; 60000]);
+                let progress = pkt_line(&progress);
+                write_chunk(&pkt_line(b"NAK\n")).unwrap();
+                write_chunk(&progress).unwrap();
+                write_chunk(&progress).unwrap();
+                thread::sleep(time::Duration::from_secs(3));
+                let _ = write_chunk(&progress).and_then(|_| write_chunk(&progress));
+                // Wait for the client to hang up.
+                let _ = stream.read_to_end(&mut Vec::new());
+            }
+        });
+        let url = server.url("/repo.git");
+
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let mut ctx = Context::testing(temp.path());
+        ctx.network.timeout = time::Duration::from_secs(1);
+        let err = lock(
+            &ctx,
+            LockMode::Normal,
+            temp.path().join("clone"),
+            &url,
+            GitCheckout::DefaultBranch,
+            &GitOptions::default(),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            format!("failed to git clone `{url}`: the transfer stalled for longer than 1s")
+        );
+    }
+
+    #[test]
+    fn lock_git_history_rewritten() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let upstream = temp.path().join("upstream.git");
+        let (url, commits) = git_init_local(&upstream, 2);
+
+        // Force push a commit that replaces the history of the branch.
+        let repo = git2::Repository::open_bare(&upstream).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let tree = repo.find_commit(commits[0]).unwrap().tree().unwrap();
+        let rewritten = repo
+            .commit(None, &sig, &sig, "Rewritten", &tree, &[])
+            .unwrap();
+        let branch = repo.head().unwrap().name().unwrap().to_string();
+        repo.reference(&branch, rewritten, true, "force push")
+            .unwrap();
+
+        // The locked commit no longer exists, so the tip is used.
+        let shallow = GitOptions {
+            depth: Some(1),
+            ..Default::default()
+        };
+        for options in [GitOptions::default(), shallow] {
+            let dir = temp.path().join("clone");
+            let _ = fs::remove_dir_all(&dir);
+            for mode in [LockMode::Normal, LockMode::Reinstall] {
+                let locked = lock(
+                    &ctx,
+                    mode,
+                    dir.clone(),
+                    &url,
+                    GitCheckout::DefaultBranch,
+                    &options,
+                    Some(commits[1]),
+                )
+                .unwrap();
+                assert_eq!(locked.commit, Some(rewritten));
+            }
+        }
+    }
+
+    #[test]
+    fn lock_git_offline() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let mut ctx = Context::testing(temp.path());
+        let upstream = temp.path().join("upstream.git");
+        let (url, commits) = git_init_local(&upstream, 3);
+        let dir = temp.path().join("clone");
+        let options = GitOptions::default();
+        lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            &url,
+            GitCheckout::DefaultBranch,
+            &options,
+            Some(commits[0]),
+        )
+        .unwrap();
+
+        // Nothing can be fetched once the upstream repository is gone.
+        fs::remove_dir_all(&upstream).unwrap();
+        ctx.offline = true;
+
+        let locked = lock(
+            &ctx,
+            LockMode::Update,
+            dir.clone(),
+            &url,
+            GitCheckout::DefaultBranch,
+            &options,
+            Some(commits[0]),
+        )
+        .unwrap();
+        assert_eq!(locked.commit, Some(commits[2]));
+
+        let err = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            &url,
+            GitCheckout::Rev("0".repeat(40)),
+            &options,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("`{url}@0000000000000000000000000000000000000000` cannot be fetched in offline mode")
+        );
+
+        let err = lock(
+            &ctx,
+            LockMode::Reinstall,
+            dir,
+            &url,
+            GitCheckout::DefaultBranch,
+            &options,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("`{url}` is not available on disk and cannot be fetched in offline mode")
+        );
+    }
+
+    #[test]
+    fn lock_git_partial() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let (url, commits) = git_init_local(&temp.path().join("upstream.git"), 3);
+        let dir = temp.path().join("clone");
+        let options = GitOptions {
+            depth: None,
+            filter: Some("blob:none".to_string()),
+            sparse: None,
+            mirror: None,
+            submodules: None,
+        };
+
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            &url,
+            GitCheckout::Rev(commits[0].to_string()),
+            &options,
+            None,
+        )
+        .unwrap();
+        assert_eq!(locked.commit, Some(commits[0]));
+        let repo = git2::Repository::open(&dir).unwrap();
+        assert!(!repo.is_shallow());
+        assert!(repo
+            .config()
+            .unwrap()
+            .get_bool("remote.origin.promisor")
+            .unwrap());
+        assert_eq!(
+            fs::read_to_string(dir.join("test.plugin.zsh")).unwrap(),
+            "echo 0\n"
+        );
+    }
+
+    #[test]
+    fn lock_git_sparse() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let upstream = temp.path().join("upstream");
+        for path in [
+            "plugins/a/a.plugin.zsh",
+            "plugins/b/b.plugin.zsh",
+            "lib/lib.zsh",
+        ] {
+            let path = upstream.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let repo = git2::Repository::init(&upstream).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "Initial commit", &tree, &[])
+            .unwrap();
+        let url = Url::from_file_path(&upstream).unwrap();
+        let dir = temp.path().join("clone");
+
+        let options = GitOptions {
+            depth: None,
+            filter: None,
+            sparse: Some(vec_into!["plugins/a", "lib"]),
+            mirror: None,
+            submodules: None,
+        };
+        lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            &url,
+            GitCheckout::DefaultBranch,
+            &options,
+            None,
+        )
+        .unwrap();
+        assert!(dir.join("plugins/a/a.plugin.zsh").is_file());
+        assert!(dir.join("lib/lib.zsh").is_file());
+        assert!(!dir.join("plugins/b").exists());
+
+        // Without any sparse paths the complete working tree is restored.
+        lock(
+            &ctx,
+            LockMode::Normal,
+            dir.clone(),
+            &url,
+            GitCheckout::DefaultBranch,
+            &GitOptions::default(),
+            None,
+        )
+        .unwrap();
+        assert!(dir.join("plugins/b/b.plugin.zsh").is_file());
+    }
+
+    #[test]
+    fn lock_git_submodules() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let (url_a, commits_a) = git_init_local(&temp.path().join("a.git"), 1);
+        let (url_b, commits_b) = git_init_local(&temp.path().join("b.git"), 1);
+
+        // A repository with a submodule at `a` and `b`.
+        let upstream = temp.path().join("upstream.git");
+        let repo = git2::Repository::init_bare(&upstream).unwrap();
+        let gitmodules = format!(
+            "[submodule \"a\"]\n\tpath = a\n\turl = {url_a}\n\
+             [submodule \"b\"]\n\tpath = b\n\turl = {url_b}\n"
+        );
+        let blob = repo.blob(gitmodules.as_bytes()).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert(".gitmodules", blob, 0o100644).unwrap();
+        builder.insert("a", commits_a[0], 0o160000).unwrap();
+        builder.insert("b", commits_b[0], 0o160000).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "Initial commit", &tree, &[])
+            .unwrap();
+        let url = Url::from_file_path(&upstream).unwrap();
+
+        for (submodules, expected) in [
+            (None, [true, true]),
+            (Some(vec!["b".to_string()]), [false, true]),
+            (Some(Vec::new()), [false, false]),
+        ] {
+            let dir = temp.path().join("clone");
+            let _ = fs::remove_dir_all(&dir);
+            let options = GitOptions {
+                submodules,
+                ..Default::default()
+            };
+            lock(
+                &ctx,
+                LockMode::Normal,
+                dir.clone(),
+                &url,
+                GitCheckout::DefaultBranch,
+                &options,
+                None,
+            )
+            .unwrap();
+            assert_eq!(
+                [
+                    dir.join("a/test.plugin.zsh").exists(),
+                    dir.join("b/test.plugin.zsh").exists()
+                ],
+                expected
+            );
+        }
+    }
+
     #[test]
     fn lock_git_and_reinstall() {
         let temp = tempfile::tempdir().expect("create temporary directory");
         let dir = temp.path();
-        let mut ctx = Context::testing(dir);
+        let ctx = Context::testing(dir);
         let url = Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap();
 
-        let locked = lock(&ctx, dir.to_path_buf(), &url, GitCheckout::DefaultBranch).unwrap();
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.to_path_buf(),
+            &url,
+            GitCheckout::DefaultBranch,
+            &GitOptions::default(),
+            None,
+        )
+        .unwrap();
 
         assert_eq!(locked.dir, dir);
         assert_eq!(locked.file, None);
@@ -215,8 +1178,16 @@ mod tests {
 
         let modified = fs::metadata(dir).unwrap().modified().unwrap();
         thread::sleep(time::Duration::from_secs(1));
-        ctx.lock_mode = Some(LockMode::Reinstall);
-        let locked = lock(&ctx, dir.to_path_buf(), &url, GitCheckout::DefaultBranch).unwrap();
+        let locked = lock(
+            &ctx,
+            LockMode::Reinstall,
+            dir.to_path_buf(),
+            &url,
+            GitCheckout::DefaultBranch,
+            &GitOptions::default(),
+            None,
+        )
+        .unwrap();
         assert_eq!(locked.dir, dir);
         assert_eq!(locked.file, None);
         let repo = git2::Repository::open(dir).unwrap();
@@ -227,6 +1198,47 @@ mod tests {
         assert!(fs::metadata(dir).unwrap().modified().unwrap() > modified);
     }
 
+    #[test]
+    fn lock_git_with_locked_commit() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let dir = temp.path();
+        let ctx = Context::testing(dir);
+        let url = Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap();
+        let commit = git2::Oid::from_str("ad149784a1538291f2477fb774eeeed4f4d29e45").unwrap();
+
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.to_path_buf(),
+            &url,
+            GitCheckout::DefaultBranch,
+            &GitOptions::default(),
+            Some(commit),
+        )
+        .unwrap();
+
+        assert_eq!(locked.commit, Some(commit));
+        let repo = git2::Repository::open(dir).unwrap();
+        assert_eq!(repo.head().unwrap().target().unwrap(), commit);
+
+        // A tag always resolves to what is configured.
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.to_path_buf(),
+            &url,
+            GitCheckout::Tag("v0.1.0".to_string()),
+            &GitOptions::default(),
+            Some(commit),
+        )
+        .unwrap();
+
+        assert_eq!(
+            locked.commit.unwrap().to_string(),
+            "be8fde277e76f35efbe46848fb352cee68549962"
+        );
+    }
+
     #[test]
     fn lock_git_https_with_checkout() {
         let temp = tempfile::tempdir().expect("create temporary directory");
@@ -234,9 +1246,12 @@ mod tests {
 
         let locked = lock(
             &Context::testing(dir),
+            LockMode::Normal,
             dir.to_path_buf(),
             &Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
             GitCheckout::Rev("ad149784a1538291f2477fb774eeeed4f4d29e45".to_string()),
+            &GitOptions::default(),
+            None,
         )
         .unwrap();
 
@@ -258,9 +1273,12 @@ mod tests {
 
         let locked = lock(
             &Context::testing(dir),
+            LockMode::Normal,
             dir.to_path_buf(),
             &Url::parse("git://github.com/rossmacarthur/sheldon-test").unwrap(),
             GitCheckout::Rev("ad149784a1538291f2477fb774eeeed4f4d29e45".to_string()),
+            &GitOptions::default(),
+            None,
         )
         .unwrap();
 
@@ -0,0 +1,539 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as ResultExt, Result};
+use globset::{Glob, GlobMatcher};
+use semver::VersionReq;
+use serde::Deserialize;
+use url::Url;
+
+use crate::config::GitReference;
+use crate::context::Context;
+use crate::lock::file::LockedExternalPlugin;
+use crate::lock::source::{archive, check_online, remote, request_options, retry, LockedSource};
+use crate::lock::LockMode;
+use crate::util;
+use crate::util::git::tag_version;
+use crate::util::TempPath;
+
+/// A GitHub release, as returned by the GitHub API.
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    draft: bool,
+    assets: Vec<Asset>,
+}
+
+/// A GitHub release asset, as returned by the GitHub API.
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: Url,
+}
+
+/// Download an asset of a GitHub release.
+///
+/// The release is the one with the configured tag, the one with the highest
+/// tag matching a version requirement, or otherwise the latest release. Unless
+/// updating, the tag in the `previous` locked plugin is used if it still
+/// matches, so that the GitHub API is only queried when something changed and
+/// a missing asset is downloaded from the locked release.
+///
+/// Each asset is downloaded into a directory named after the asset inside
+/// `releases_dir/<tag>`. Archives are extracted into this directory, other
+/// assets are made executable.
+#[allow(clippy::too_many_arguments)]
+pub fn lock(
+    ctx: &Context,
+    mode: LockMode,
+    releases_dir: PathBuf,
+    url: &Url,
+    reference: Option<&GitReference>,
+    asset: &str,
+    sha256: Option<&str>,
+    strip_components: usize,
+    previous: Option<&LockedExternalPlugin>,
+) -> Result<LockedSource> {
+    let matcher = Glob::new(asset)
+        .with_context(|| format!("invalid asset pattern `{asset}`"))?
+        .compile_matcher();
+    let previous_tag = previous.and_then(|p| p.tag.as_deref());
+    let previous_sha256 = previous.and_then(|p| p.sha256.as_deref());
+
+    // The tag that can be used without querying the GitHub API.
+    let locked_tag = match reference {
+        Some(GitReference::Tag(tag)) => Some(tag.as_str()),
+        Some(GitReference::Version(req)) => {
+            let req = VersionReq::parse(req)
+                .with_context(|| format!("failed to parse version requirement `{req}`"))?;
+            previous_tag.filter(|tag| matches!(tag_version(tag), Some(v) if req.matches(&v)))
+        }
+        Some(GitReference::Branch(_) | GitReference::Rev(_)) => {
+            bail!("GitHub releases can only be selected by tag or version")
+        }
+        None => previous_tag,
+    };
+
+    // In offline mode a downloaded asset is always used.
+    if matches!(mode, LockMode::Normal | LockMode::Frozen) || ctx.offline {
+        let checked = locked_tag.and_then(|tag| {
+            let dir = find_asset_dir(&releases_dir.join(tag), &matcher)?;
+            let checksum = previous_sha256.filter(|_| previous_tag == Some(tag));
+            if sha256.is_some() && sha256 != checksum {
+                return None;
+            }
+            Some((tag, dir, checksum))
+        });
+        match checked {
+            Some((tag, dir, checksum)) => {
+                ctx.log_status("Checked", &format!("{url}@{tag}"));
+                return Ok(LockedSource {
+                    dir,
+                    file: None,
+                    commit: None,
+                    tag: Some(tag.to_string()),
+                    sha256: checksum.map(str::to_string),
+                    log: None,
+                });
+            }
+            None if matches!(mode, LockMode::Frozen) => {
+                bail!("no release asset matching `{asset}` is downloaded for `{url}`")
+            }
+            None => {}
+        }
+    }
+
+    check_online(ctx, url)?;
+    // Unless updating or reinstalling, the locked release is downloaded so
+    // that the lock file is honored when the asset is not downloaded yet.
+    let tag = match (mode, reference) {
+        (_, Some(GitReference::Tag(tag))) => Some(tag.as_str()),
+        (LockMode::Normal | LockMode::Frozen, _) => locked_tag,
+        (LockMode::Update | LockMode::Reinstall, _) => None,
+    };
+    let release = match (tag, reference) {
+        (Some(tag), _) => fetch_release(ctx, &format!("{url}/releases/tags/{tag}"))?,
+        (None, Some(GitReference::Version(req))) => latest_matching_release(ctx, url, req)?,
+        _ => fetch_release(ctx, &format!("{url}/releases/latest"))?,
+    };
+    let found = release
+        .assets
+        .iter()
+        .find(|a| matcher.is_match(&a.name))
+        .with_context(|| {
+            format!(
+                "failed to find an asset matching `{asset}` in release `{}`, found: {}",
+                release.tag_name,
+                release
+                    .assets
+                    .iter()
+                    .map(|a| format!("`{}`", a.name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+
+    let dir = releases_dir.join(&release.tag_name).join(&found.name);
+    let temp_dir =
+        TempPath::new_force(&dir).context("failed to prepare temporary download directory")?;
+    let file = temp_dir.path().join(&found.name);
+    let checksum = remote::download(
+        ctx,
+        temp_dir.path(),
+        &file,
+        &found.browser_download_url,
+        sha256,
+    )?;
+    if archive::is_archive(&found.name) {
+        archive::extract_file(&file, temp_dir.path(), strip_components)?;
+        fs::remove_file(&file).with_context(|| format!("failed to remove `{}`", file.display()))?;
+    } else {
+        make_executable(&file)
+            .with_context(|| format!("failed to make `{}` executable", file.display()))?;
+    }
+    temp_dir
+        .rename(&dir)
+        .context("failed to rename temporary download directory")?;
+    remote::log_fetched(ctx, &found.browser_download_url, &checksum, previous_sha256);
+
+    Ok(LockedSource {
+        dir,
+        file: None,
+        commit: None,
+        tag: Some(release.tag_name),
+        sha256: Some(checksum),
+        log: None,
+    })
+}
+
+/// Returns the directory of a previously downloaded asset matching the given
+/// pattern.
+fn find_asset_dir(tag_dir: &Path, matcher: &GlobMatcher) -> Option<PathBuf> {
+    fs::read_dir(tag_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| {
+            let name = entry.file_name();
+            !name.to_string_lossy().starts_with('~') && matcher.is_match(&name)
+        })
+        .map(|entry| entry.path())
+}
+
+/// Make a downloaded binary executable.
+#[cfg(unix)]
+fn make_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+/// Files don't have an executable permission on other platforms.
+#[cfg(not(unix))]
+fn make_executable(_: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Fetch the response to a GitHub API request.
+fn fetch_json(ctx: &Context, url: &str) -> Result<util::JsonResponse> {
+    let parsed = Url::parse(url).with_context(|| format!("failed to parse URL `{url}`"))?;
+    let request = request_options(ctx, &parsed)?;
+    retry(ctx, url, || {
+        util::fetch_json(url, &request).with_context(|| format!("failed to fetch `{url}`"))
+    })
+}
+
+fn fetch_release(ctx: &Context, url: &str) -> Result<Release> {
+    let response = fetch_json(ctx, url)?;
+    serde_json::from_slice(&response.body)
+        .with_context(|| format!("failed to parse release from `{url}`"))
+}
+
+/// Returns the release with the highest tag matching the version requirement.
+///
+/// The releases are listed newest first, so the pages are only fetched until
+/// one of them has a matching release.
+fn latest_matching_release(ctx: &Context, url: &Url, req: &str) -> Result<Release> {
+    let req = VersionReq::parse(req)
+        .with_context(|| format!("failed to parse version requirement `{req}`"))?;
+    let mut next = Some(format!("{url}/releases?per_page=100"));
+    while let Some(releases_url) = next {
+        let response = fetch_json(ctx, &releases_url)?;
+        let releases: Vec<Release> = serde_json::from_slice(&response.body)
+            .with_context(|| format!("failed to parse releases from `{releases_url}`"))?;
+        let latest = releases
+            .into_iter()
+            .filter(|release| !release.draft)
+            .filter_map(|release| Some((tag_version(&release.tag_name)?, release)))
+            .filter(|(version, _)| req.matches(version))
+            .max_by(|a, b| a.0.cmp(&b.0));
+        if let Some((_, release)) = latest {
+            return Ok(release);
+        }
+        next = response.next;
+    }
+    bail!("failed to find a release matching version `{req}`")
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Unit tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::util::test_server::{self, Server};
+
+    /// A local HTTP server standing in for the GitHub API, serving fixed
+    /// responses by path.
+    fn api_server() -> Server {
+        Server::new(|request, stream| {
+            let server_url = format!("http://{}", request.header("Host").unwrap());
+            // The first page of releases links to the second.
+            let link = format!(
+                "<{server_url}/repos/owner/tool/releases?per_page=100&page=2>; rel=\"next\", \
+                 <{server_url}/repos/owner/tool/releases?per_page=100&page=2>; rel=\"last\""
+            );
+            let headers: &[(&str, &str)] = match request.path.as_str() {
+                "/repos/owner/tool/releases?per_page=100" => &[("Link", &link)],
+                _ => &[],
+            };
+            match routes(&server_url).get(&request.path) {
+                Some(body) => test_server::respond(stream, "200 OK", headers, body),
+                None => test_server::respond(stream, "404 Not Found", &[], &[]),
+            }
+        })
+    }
+
+    fn api_url(server: &Server) -> Url {
+        server.url("/repos/owner/tool")
+    }
+
+    fn release_json(server_url: &str, tag: &str) -> String {
+        format!(
+            r#"{{
+                "tag_name": "{tag}",
+                "assets": [
+                    {{
+                        "name": "tool-linux",
+                        "browser_download_url": "{server_url}/download/{tag}/tool-linux"
+                    }},
+                    {{
+                        "name": "tool-darwin.tar.gz",
+                        "browser_download_url": "{server_url}/download/{tag}/tool-darwin.tar.gz"
+                    }}
+                ]
+            }}"#
+        )
+    }
+
+    /// The responses for a repository with the releases `v1.0.0`, `v1.1.0`
+    /// and `v2.0.0`, listed two per page.
+    fn routes(server_url: &str) -> HashMap<String, Vec<u8>> {
+        let tags = ["v2.0.0", "v1.1.0", "v1.0.0"];
+        let mut routes = HashMap::new();
+        for tag in &tags {
+            routes.insert(
+                format!("/repos/owner/tool/releases/tags/{tag}"),
+                release_json(server_url, tag).into_bytes(),
+            );
+            routes.insert(
+                format!("/download/{tag}/tool-linux"),
+                format!("#!/bin/sh\necho {tag}\n").into_bytes(),
+            );
+        }
+        routes.insert(
+            "/repos/owner/tool/releases/latest".into(),
+            release_json(server_url, tags[0]).into_bytes(),
+        );
+        let releases: Vec<_> = tags
+            .iter()
+            .map(|tag| release_json(server_url, tag))
+            .collect();
+        routes.insert(
+            "/repos/owner/tool/releases?per_page=100".into(),
+            format!("[{}]", releases[..2].join(",")).into_bytes(),
+        );
+        routes.insert(
+            "/repos/owner/tool/releases?per_page=100&page=2".into(),
+            format!("[{}]", releases[2..].join(",")).into_bytes(),
+        );
+        routes
+    }
+
+    fn previous(locked: &LockedSource) -> LockedExternalPlugin {
+        LockedExternalPlugin {
+            name: "tool".into(),
+            source_dir: locked.dir.clone(),
+            commit: None,
+            tag: locked.tag.clone(),
+            sha256: locked.sha256.clone(),
+            plugin_dir: None,
+            files: Vec::new(),
+            apply: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn lock_github_release_version() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let server = api_server();
+        let url = api_url(&server);
+        let releases_dir = temp.path().join("releases");
+        let reference = GitReference::Version("1".into());
+
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            releases_dir.clone(),
+            &url,
+            Some(&reference),
+            "*-linux",
+            None,
+            0,
+            None,
+        )
+        .unwrap();
+        let file = releases_dir.join("v1.1.0/tool-linux/tool-linux");
+        assert_eq!(locked.dir, releases_dir.join("v1.1.0/tool-linux"));
+        assert_eq!(locked.tag.as_deref(), Some("v1.1.0"));
+        assert_eq!(locked.sha256, Some(util::sha256(&file).unwrap()));
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "#!/bin/sh\necho v1.1.0\n"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                fs::metadata(&file).unwrap().permissions().mode() & 0o777,
+                0o755
+            );
+        }
+        assert_eq!(
+            server.take_requests(),
+            [
+                "/repos/owner/tool/releases?per_page=100",
+                "/download/v1.1.0/tool-linux"
+            ]
+        );
+
+        // The previously locked release is used without querying the API.
+        let previous = previous(&locked);
+        let relocked = lock(
+            &ctx,
+            LockMode::Normal,
+            releases_dir.clone(),
+            &url,
+            Some(&reference),
+            "*-linux",
+            None,
+            0,
+            Some(&previous),
+        )
+        .unwrap();
+        assert_eq!(relocked.dir, locked.dir);
+        assert_eq!(relocked.sha256, locked.sha256);
+        assert!(server.take_requests().is_empty());
+
+        // Updating fetches the latest release.
+        let updated = lock(
+            &ctx,
+            LockMode::Update,
+            releases_dir,
+            &url,
+            None,
+            "tool-linux",
+            None,
+            0,
+            Some(&previous),
+        )
+        .unwrap();
+        assert_eq!(updated.tag.as_deref(), Some("v2.0.0"));
+        assert_eq!(
+            server.take_requests(),
+            [
+                "/repos/owner/tool/releases/latest",
+                "/download/v2.0.0/tool-linux"
+            ]
+        );
+    }
+
+    #[test]
+    fn lock_github_release_locked_tag() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let server = api_server();
+        let url = api_url(&server);
+        let releases_dir = temp.path().join("releases");
+        let previous = LockedExternalPlugin {
+            name: "tool".into(),
+            source_dir: releases_dir.join("v1.0.0/tool-linux"),
+            commit: None,
+            tag: Some("v1.0.0".into()),
+            sha256: None,
+            plugin_dir: None,
+            files: Vec::new(),
+            apply: Vec::new(),
+        };
+
+        // The asset of the locked release is downloaded, not the latest one.
+        for reference in [None, Some(GitReference::Version("1".into()))] {
+            let _ = fs::remove_dir_all(&releases_dir);
+            let locked = lock(
+                &ctx,
+                LockMode::Normal,
+                releases_dir.clone(),
+                &url,
+                reference.as_ref(),
+                "tool-linux",
+                None,
+                0,
+                Some(&previous),
+            )
+            .unwrap();
+            assert_eq!(locked.tag.as_deref(), Some("v1.0.0"));
+            assert_eq!(
+                server.take_requests(),
+                [
+                    "/repos/owner/tool/releases/tags/v1.0.0",
+                    "/download/v1.0.0/tool-linux"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn lock_github_release_missing_asset() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let server = api_server();
+        let reference = GitReference::Tag("v1.0.0".into());
+
+        let err = lock(
+            &ctx,
+            LockMode::Normal,
+            temp.path().join("releases"),
+            &api_url(&server),
+            Some(&reference),
+            "*.zip",
+            None,
+            0,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to find an asset matching `*.zip` in release `v1.0.0`, found: `tool-linux`, \
+             `tool-darwin.tar.gz`"
+        );
+    }
+
+    #[test]
+    fn lock_github_release_version_next_page() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let ctx = Context::testing(temp.path());
+        let server = api_server();
+        let releases_dir = temp.path().join("releases");
+        let lock = |version: &str| {
+            lock(
+                &ctx,
+                LockMode::Normal,
+                releases_dir.clone(),
+                &api_url(&server),
+                Some(&GitReference::Version(version.into())),
+                "*-linux",
+                None,
+                0,
+                None,
+            )
+        };
+
+        let locked = lock("~1.0").unwrap();
+        assert_eq!(locked.tag.as_deref(), Some("v1.0.0"));
+        assert_eq!(
+            server.take_requests(),
+            [
+                "/repos/owner/tool/releases?per_page=100",
+                "/repos/owner/tool/releases?per_page=100&page=2",
+                "/download/v1.0.0/tool-linux"
+            ]
+        );
+
+        let err = lock("3").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to find a release matching version `^3`"
+        );
+        assert_eq!(
+            server.take_requests(),
+            [
+                "/repos/owner/tool/releases?per_page=100",
+                "/repos/owner/tool/releases?per_page=100&page=2"
+            ]
+        );
+    }
+}
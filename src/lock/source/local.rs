@@ -11,7 +11,14 @@ pub fn lock(ctx: &Context, dir: PathBuf) -> Result<LockedSource> {
 
     if dir.exists() && dir.is_dir() {
         ctx.log_status("Checked", dir.as_path());
-        Ok(LockedSource { dir, file: None })
+        Ok(LockedSource {
+            dir,
+            file: None,
+            commit: None,
+            tag: None,
+            sha256: None,
+            log: None,
+        })
     } else if let Ok(walker) = globwalk::glob(dir.to_string_lossy()) {
         let mut directories: Vec<_> = walker
             .filter_map(|result| match result {
@@ -23,7 +30,14 @@ pub fn lock(ctx: &Context, dir: PathBuf) -> Result<LockedSource> {
         if directories.len() == 1 {
             let dir = directories.remove(0);
             ctx.log_status("Checked", dir.as_path());
-            Ok(LockedSource { dir, file: None })
+            Ok(LockedSource {
+                dir,
+                file: None,
+                commit: None,
+                tag: None,
+                sha256: None,
+                log: None,
+            })
         } else {
             Err(anyhow!(
                 "`{}` matches {} directories",
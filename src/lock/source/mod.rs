@@ -1,16 +1,30 @@
+pub mod archive;
+mod command;
 mod git;
+mod github_release;
 mod local;
 mod remote;
 
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
 use std::fmt;
+use std::fmt::Write;
 use std::path::PathBuf;
+use std::{env, fs, thread};
 
-use anyhow::{Context as ResultExt, Result};
+use anyhow::{bail, Context as ResultExt, Result};
+use sha2::{Digest, Sha256};
 use url::Url;
 
-use crate::config::Source;
+use crate::config::{ExternalPlugin, GitOptions, GitReference, Plugin, Source};
 use crate::context::Context;
+use crate::lock::file::LockedExternalPlugin;
+pub use crate::lock::source::command::stamp_file;
 use crate::lock::source::git::GitCheckout;
+pub use crate::lock::source::git::{GitLog, Outdated};
+pub use crate::lock::source::remote::validators_file;
+use crate::lock::LockMode;
+use crate::util::{netrc, ssh, RequestOptions};
 
 /// A locked `Source`.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -19,40 +33,266 @@ pub struct LockedSource {
     pub dir: PathBuf,
     /// The downloaded file.
     pub file: Option<PathBuf>,
+    /// The Git commit that was checked out.
+    pub commit: Option<git2::Oid>,
+    /// The Git tag that was chosen for a version requirement.
+    pub tag: Option<String>,
+    /// The SHA-256 checksum of the downloaded file.
+    pub sha256: Option<String>,
+    /// The commits that changed, if the Git source was moved.
+    pub log: Option<GitLog>,
 }
 
 // Install a source.
-pub fn lock(ctx: &Context, src: Source) -> Result<LockedSource> {
+//
+// If the source was previously locked then Git sources are checked out at the
+// previously locked commit and changes to remote files are reported. Git
+// repositories in `multi_ref` are cloned into a separate directory for each
+// ref, see [`multi_ref_repos`].
+pub fn lock(
+    ctx: &Context,
+    mode: LockMode,
+    src: Source,
+    multi_ref: &HashSet<Url>,
+    previous: Option<&LockedExternalPlugin>,
+) -> Result<LockedSource> {
     match src {
-        Source::Git { url, reference } => {
-            let dir = git_dir(ctx, &url)?;
-            git::lock(ctx, dir, &url, reference.into())
+        Source::Git {
+            url,
+            reference,
+            options,
+        } => {
+            let dir = git_dir(ctx, &url, reference.as_ref(), multi_ref)?;
+            // The locked commit is only used if the plugin still uses the same
+            // clone, otherwise it belongs to a different repository or ref.
+            let commit = previous
+                .filter(|p| p.source_dir == dir)
+                .and_then(|p| p.commit.as_deref())
+                .and_then(|c| git2::Oid::from_str(c).ok());
+            let url = options.mirror.as_ref().unwrap_or(&url);
+            git::lock(ctx, mode, dir, url, reference.into(), &options, commit)
         }
 
-        Source::Remote { url } => {
+        Source::Remote {
+            url,
+            mirror,
+            sha256,
+        } => {
             let (dir, file) = remote_dir_and_file(ctx, &url)?;
-            remote::lock(ctx, dir, file, &url)
+            let url = mirror.as_ref().unwrap_or(&url);
+            let previous = previous.and_then(|p| p.sha256.as_deref());
+            remote::lock(ctx, mode, dir, file, url, sha256.as_deref(), previous)
+        }
+
+        Source::Archive {
+            url,
+            mirror,
+            sha256,
+            strip_components,
+        } => {
+            let (_, file) = remote_dir_and_file(ctx, &url)?;
+            let dir = archive::extract_dir(&file)?;
+            let url = mirror.as_ref().unwrap_or(&url);
+            let previous = previous.and_then(|p| p.sha256.as_deref());
+            archive::lock(
+                ctx,
+                mode,
+                dir,
+                file,
+                url,
+                sha256.as_deref(),
+                strip_components,
+                previous,
+            )
+        }
+
+        Source::GitHubRelease {
+            url,
+            reference,
+            asset,
+            sha256,
+            strip_components,
+        } => {
+            let dir = releases_dir(ctx, &url)?;
+            github_release::lock(
+                ctx,
+                mode,
+                dir,
+                &url,
+                reference.as_ref(),
+                &asset,
+                sha256.as_deref(),
+                strip_components,
+                previous,
+            )
         }
 
         Source::Local { dir } => local::lock(ctx, dir),
+
+        Source::Command { command } => {
+            let (dir, file) = (command_dir(ctx), command_file(ctx, &command));
+            let previous = previous.and_then(|p| p.sha256.as_deref());
+            command::lock(ctx, mode, dir, file, &command, previous)
+        }
+    }
+}
+
+/// Check whether a source is behind upstream, without checking anything out.
+///
+/// Returns `None` if the source is not a Git repository.
+pub fn outdated(ctx: &Context, src: &Source, multi_ref: &HashSet<Url>) -> Result<Option<Outdated>> {
+    match src {
+        Source::Git {
+            url,
+            reference,
+            options,
+        } => {
+            let dir = git_dir(ctx, url, reference.as_ref(), multi_ref)?;
+            let url = options.mirror.as_ref().unwrap_or(url);
+            git::outdated(ctx, &dir, url, &reference.clone().into(), options).map(Some)
+        }
+        Source::Remote { .. }
+        | Source::Archive { .. }
+        | Source::GitHubRelease { .. }
+        | Source::Local { .. }
+        | Source::Command { .. } => Ok(None),
     }
 }
 
 impl fmt::Display for Source {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Git { url, reference } => {
+            Self::Git { url, reference, .. } => {
+                let checkout: GitCheckout = reference.clone().into();
+                write!(f, "{url}{checkout}")
+            }
+            Self::Remote { url, .. } | Self::Archive { url, .. } => write!(f, "{url}"),
+            Self::GitHubRelease { url, reference, .. } => {
                 let checkout: GitCheckout = reference.clone().into();
                 write!(f, "{url}{checkout}")
             }
-            Self::Remote { url, .. } => write!(f, "{url}"),
             Self::Local { dir } => write!(f, "{}", dir.display()),
+            Self::Command { command } => write!(f, "{command}"),
+        }
+    }
+}
+
+/// Returns the key that the plugins using a source are grouped by, so that each
+/// group is locked once.
+///
+/// Git sources with the same URL and reference share a clone, so they are
+/// grouped regardless of their options, see [`GitOptions::merge`].
+pub fn group_key(source: &Source) -> Source {
+    match source {
+        Source::Git { url, reference, .. } => Source::Git {
+            url: url.clone(),
+            reference: reference.clone(),
+            options: GitOptions::default(),
+        },
+        source => source.clone(),
+    }
+}
+
+/// Returns the source to lock for a group of plugins, see [`group_key`].
+pub fn group_source<'a, I>(source: &Source, others: I) -> Source
+where
+    I: IntoIterator<Item = &'a Source>,
+{
+    let mut source = source.clone();
+    if let Source::Git { options, .. } = &mut source {
+        for other in others {
+            if let Source::Git { options: other, .. } = other {
+                options.merge(other);
+            }
+        }
+    }
+    source
+}
+
+/// Returns the Git repositories that are used at more than one ref by the
+/// given plugins.
+pub fn multi_ref_repos(plugins: &[Plugin]) -> HashSet<Url> {
+    let mut refs: HashMap<&Url, HashSet<Option<&GitReference>>> = HashMap::new();
+    for plugin in plugins {
+        if let Plugin::External(ExternalPlugin {
+            source: Source::Git { url, reference, .. },
+            ..
+        }) = plugin
+        {
+            refs.entry(url).or_default().insert(reference.as_ref());
         }
     }
+    refs.into_iter()
+        .filter(|(_, refs)| refs.len() > 1)
+        .map(|(url, _)| url.clone())
+        .collect()
 }
 
-pub fn git_dir(ctx: &Context, url: &Url) -> Result<PathBuf> {
+/// Returns the directory to clone a Git repository into.
+///
+/// This is the host and path of the URL inside the clone directory. Local
+/// repositories, e.g. `file:///srv/git/repo.git`, don't have a host so they
+/// are cloned into the `localhost` directory.
+///
+/// If the repository is used at more than one ref then each ref, other than
+/// the default branch, is cloned into its own directory with the ref appended
+/// to the name, e.g. `sheldon-test@v0.1.0`.
+pub fn git_dir(
+    ctx: &Context,
+    url: &Url,
+    reference: Option<&GitReference>,
+    multi_ref: &HashSet<Url>,
+) -> Result<PathBuf> {
     let mut dir = ctx.clone_dir().to_path_buf();
+    match url.host_str().filter(|host| !host.is_empty()) {
+        Some(host) => dir.push(host),
+        None if url.scheme() == "file" => dir.push("localhost"),
+        None => bail!("URL `{url}` has no host"),
+    }
+    dir.push(url.path().trim_start_matches('/'));
+    match reference {
+        Some(reference) if multi_ref.contains(url) => {
+            let (GitReference::Branch(s)
+            | GitReference::Rev(s)
+            | GitReference::Tag(s)
+            | GitReference::Version(s)) = reference;
+            let mut name = dir
+                .file_name()
+                .with_context(|| format!("URL `{url}` has no path"))?
+                .to_os_string();
+            name.push("@");
+            name.push(escape_ref(s));
+            dir.set_file_name(name);
+        }
+        _ => {}
+    }
+    Ok(dir)
+}
+
+/// Escape a Git reference so that it can be used in a directory name.
+///
+/// Characters other than ASCII alphanumerics, `.`, `-`, and `_` are percent
+/// encoded, so that for example a branch `feature/a` doesn't result in a
+/// nested directory.
+fn escape_ref(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b'_') {
+            escaped.push(char::from(b));
+        } else {
+            write!(escaped, "%{b:02X}").unwrap();
+        }
+    }
+    escaped
+}
+
+/// Returns the directory that the assets of a GitHub repository's releases
+/// are downloaded into.
+///
+/// This is the host and path of the API URL inside the download directory,
+/// each release is downloaded into a subdirectory named after its tag.
+pub fn releases_dir(ctx: &Context, url: &Url) -> Result<PathBuf> {
+    let mut dir = ctx.download_dir().to_path_buf();
     dir.push(
         url.host_str()
             .with_context(|| format!("URL `{url}` has no host"))?,
@@ -79,6 +319,121 @@ pub fn remote_dir_and_file(ctx: &Context, url: &Url) -> Result<(PathBuf, PathBuf
     Ok((dir, file))
 }
 
+/// Returns the directory that the output of commands is cached in.
+pub fn command_dir(ctx: &Context) -> PathBuf {
+    ctx.data_dir().join("commands")
+}
+
+/// Returns the file that the output of a command is cached in.
+///
+/// The file is named after the checksum of the command.
+pub fn command_file(ctx: &Context, command: &str) -> PathBuf {
+    let name = format!("{:x}", Sha256::digest(command.as_bytes()));
+    command_dir(ctx).join(&name[..16])
+}
+
+/// The options for requests to the given URL.
+///
+/// Credentials come from the token configured for the host, otherwise from the
+/// netrc file if it has an entry for the host. SSH keys come from the host's
+/// `ssh_key`, then the SSH config file, then the default identity files.
+fn request_options(ctx: &Context, url: &Url) -> Result<RequestOptions> {
+    request_options_with_env(ctx, url, |name| env::var_os(name))
+}
+
+/// The options for requests to the given URL, reading environment variables
+/// using the given function.
+fn request_options_with_env<F>(ctx: &Context, url: &Url, var: F) -> Result<RequestOptions>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    let host = url.host_str().unwrap_or_default();
+    let options = ctx.network.hosts.get(host);
+    let headers = options
+        .into_iter()
+        .flat_map(|o| &o.headers)
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect();
+    let credentials = match options.and_then(|o| o.token_env.as_deref()) {
+        Some(name) => {
+            let token = var(name)
+                .and_then(|token| token.into_string().ok())
+                .with_context(|| {
+                    format!("failed to read token for `{host}` from environment variable `{name}`")
+                })?;
+            let username = options.and_then(|o| o.username.as_deref());
+            Some((username.unwrap_or("oauth2").to_string(), token))
+        }
+        None => {
+            let path = var("NETRC")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| ctx.home.join(".netrc"));
+            fs::read_to_string(path)
+                .ok()
+                .and_then(|contents| netrc::find(&contents, host))
+        }
+    };
+    let ssh_key = options
+        .and_then(|o| o.ssh_key.clone())
+        .map(|path| ctx.expand_tilde(path));
+    let ssh_dir = ctx.home.join(".ssh");
+    let identity_files = fs::read_to_string(ssh_dir.join("config"))
+        .map(|contents| ssh::identity_files(&contents, host, &ctx.home))
+        .unwrap_or_default()
+        .into_iter()
+        .chain(ssh::DEFAULT_IDENTITY_FILES.iter().map(|f| ssh_dir.join(f)))
+        .filter(|path| path.is_file())
+        .collect();
+    Ok(RequestOptions {
+        timeout: ctx.network.timeout,
+        credentials,
+        headers,
+        ssh_key,
+        identity_files,
+    })
+}
+
+/// Call `f` until it succeeds, retrying as many times as configured.
+///
+/// The wait between attempts starts at the configured backoff and doubles
+/// after each attempt.
+fn retry<T, U, F>(ctx: &Context, url: U, mut f: F) -> Result<T>
+where
+    U: fmt::Display,
+    F: FnMut() -> Result<T>,
+{
+    let attempts = ctx.network.retries.saturating_add(1);
+    let mut backoff = ctx.network.backoff;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < attempts => {
+                ctx.log_verbose_warning("Failed", &format!("{url}: {err:#}"));
+                thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+                attempt += 1;
+                ctx.log_warning(
+                    "Retrying",
+                    &format!("{url} (attempt {attempt} of {attempts})"),
+                );
+            }
+            Err(err) if attempts > 1 => {
+                return Err(err.context(format!("failed after {attempts} attempts")));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Returns an error if the network is not allowed to be used.
+fn check_online(ctx: &Context, url: &Url) -> Result<()> {
+    if ctx.offline {
+        bail!("`{url}` is not available on disk and cannot be fetched in offline mode");
+    }
+    Ok(())
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Unit tests
 ////////////////////////////////////////////////////////////////////////////////
@@ -87,9 +442,13 @@ pub fn remote_dir_and_file(ctx: &Context, url: &Url) -> Result<(PathBuf, PathBuf
 mod tests {
     use super::*;
 
+    use std::path::Path;
+    use std::time::Duration;
+
+    use indexmap::indexmap;
     use url::Url;
 
-    use crate::config::GitReference;
+    use crate::config::{GitOptions, HostOptions};
 
     #[test]
     fn source_to_string() {
@@ -97,6 +456,7 @@ mod tests {
             Source::Git {
                 url: Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
                 reference: Some(GitReference::Tag("v0.3.0".to_string())),
+                options: GitOptions::default(),
             }
             .to_string(),
             "https://github.com/rossmacarthur/sheldon-test@v0.3.0"
@@ -105,6 +465,7 @@ mod tests {
             Source::Git {
                 url: Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
                 reference: None,
+                options: GitOptions::default(),
             }
             .to_string(),
             "https://github.com/rossmacarthur/sheldon-test"
@@ -113,6 +474,8 @@ mod tests {
             Source::Remote {
                 url: Url::parse("https://github.com/rossmacarthur/sheldon/raw/0.3.0/LICENSE-MIT")
                     .unwrap(),
+                mirror: None,
+                sha256: None,
             }
             .to_string(),
             "https://github.com/rossmacarthur/sheldon/raw/0.3.0/LICENSE-MIT"
@@ -126,6 +489,214 @@ mod tests {
         );
     }
 
+    fn git_plugin(name: &str, url: &str, reference: Option<GitReference>) -> Plugin {
+        Plugin::External(ExternalPlugin {
+            name: name.into(),
+            source: Source::Git {
+                url: Url::parse(url).unwrap(),
+                reference,
+                options: GitOptions::default(),
+            },
+            dir: None,
+            sparse: None,
+            uses: None,
+            apply: None,
+            profiles: None,
+        })
+    }
+
+    #[test]
+    fn multi_ref_repos_only_includes_repos_with_different_refs() {
+        let url = "https://github.com/rossmacarthur/sheldon-test";
+        let other = "https://github.com/rossmacarthur/other";
+        let plugins = [
+            git_plugin("a", url, None),
+            git_plugin("b", url, Some(GitReference::Tag("v0.1.0".into()))),
+            git_plugin("c", other, Some(GitReference::Tag("v0.1.0".into()))),
+            git_plugin("d", other, Some(GitReference::Tag("v0.1.0".into()))),
+        ];
+        assert_eq!(
+            multi_ref_repos(&plugins),
+            HashSet::from([Url::parse(url).unwrap()])
+        );
+    }
+
+    #[test]
+    fn group_source_merges_git_options() {
+        let url = Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap();
+        let git = |depth, filter: Option<&str>, submodules: Option<&[&str]>| Source::Git {
+            url: url.clone(),
+            reference: None,
+            options: GitOptions {
+                depth,
+                filter: filter.map(str::to_string),
+                submodules: submodules.map(|paths| paths.iter().map(|p| p.to_string()).collect()),
+                ..Default::default()
+            },
+        };
+        let a = git(Some(1), Some("blob:none"), Some(&["a"]));
+        let b = git(Some(5), Some("blob:none"), Some(&["b", "a"]));
+        let c = git(None, Some("tree:0"), None);
+
+        assert_eq!(group_key(&a), group_key(&c));
+        assert_eq!(
+            group_source(&a, [&b]),
+            git(Some(5), Some("blob:none"), Some(&["a", "b"]))
+        );
+        assert_eq!(group_source(&a, [&b, &c]), git(None, None, None));
+    }
+
+    #[test]
+    fn git_dir_with_multiple_refs() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let dir = temp.path();
+        let ctx = Context::testing(dir);
+        let url = Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap();
+        let multi_ref = HashSet::from([url.clone()]);
+        let tag = GitReference::Tag("v0.1.0".into());
+        let branch = GitReference::Branch("feature/a".into());
+
+        assert_eq!(
+            git_dir(&ctx, &url, Some(&tag), &HashSet::new()).unwrap(),
+            dir.join("repos/github.com/rossmacarthur/sheldon-test")
+        );
+        assert_eq!(
+            git_dir(&ctx, &url, None, &multi_ref).unwrap(),
+            dir.join("repos/github.com/rossmacarthur/sheldon-test")
+        );
+        assert_eq!(
+            git_dir(&ctx, &url, Some(&tag), &multi_ref).unwrap(),
+            dir.join("repos/github.com/rossmacarthur/sheldon-test@v0.1.0")
+        );
+        assert_eq!(
+            git_dir(&ctx, &url, Some(&branch), &multi_ref).unwrap(),
+            dir.join("repos/github.com/rossmacarthur/sheldon-test@feature%2Fa")
+        );
+    }
+
+    #[test]
+    fn git_dir_without_host() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let dir = temp.path();
+        let ctx = Context::testing(dir);
+
+        let url = Url::parse("file:///srv/git/plugins.git").unwrap();
+        assert_eq!(
+            git_dir(&ctx, &url, None, &HashSet::new()).unwrap(),
+            dir.join("repos/localhost/srv/git/plugins.git")
+        );
+        let url = Url::parse("file://nas/srv/git/plugins.git").unwrap();
+        assert_eq!(
+            git_dir(&ctx, &url, None, &HashSet::new()).unwrap(),
+            dir.join("repos/nas/srv/git/plugins.git")
+        );
+        let url = Url::parse("data:text/plain,test").unwrap();
+        assert_eq!(
+            git_dir(&ctx, &url, None, &HashSet::new())
+                .unwrap_err()
+                .to_string(),
+            "URL `data:text/plain,test` has no host"
+        );
+    }
+
+    #[test]
+    fn lock_with_local_bare_repository() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let dir = temp.path();
+        let ctx = Context::testing(dir);
+
+        let bare_dir = dir.join("srv/git/plugins.git");
+        let bare = git2::Repository::init_bare(&bare_dir).unwrap();
+        let blob = bare.blob(b"echo 'testing...'\n").unwrap();
+        let mut builder = bare.treebuilder(None).unwrap();
+        builder.insert("test.plugin.zsh", blob, 0o100644).unwrap();
+        let tree = bare.find_tree(builder.write().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let commit = bare
+            .commit(Some("HEAD"), &sig, &sig, "Initial commit", &tree, &[])
+            .unwrap();
+
+        let url = Url::from_file_path(&bare_dir).unwrap();
+        let source = Source::Git {
+            url,
+            reference: None,
+            options: GitOptions::default(),
+        };
+        let locked = lock(&ctx, LockMode::Normal, source, &HashSet::new(), None).unwrap();
+
+        let clone_dir = dir
+            .join("repos/localhost")
+            .join(bare_dir.strip_prefix("/").unwrap());
+        assert_eq!(locked.dir, clone_dir);
+        assert_eq!(locked.commit, Some(commit));
+        assert!(clone_dir.join("test.plugin.zsh").is_file());
+    }
+
+    #[test]
+    fn lock_git_with_changed_url() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let dir = temp.path();
+        let ctx = Context::testing(dir);
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let commit = |repo: &git2::Repository, parents: &[&git2::Commit<'_>]| {
+            let blob = repo.blob(b"echo 'testing...'\n").unwrap();
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("test.plugin.zsh", blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            repo.commit(Some("HEAD"), &sig, &sig, "Commit", &tree, parents)
+                .unwrap()
+        };
+
+        // A fork that is one commit ahead of the original repository.
+        let original_dir = dir.join("srv/git/original.git");
+        let original = git2::Repository::init_bare(&original_dir).unwrap();
+        let original_commit = commit(&original, &[]);
+        let fork_dir = dir.join("srv/git/fork.git");
+        let fork = git2::Repository::init_bare(&fork_dir).unwrap();
+        fork.remote_anonymous(original_dir.to_str().unwrap())
+            .unwrap()
+            .fetch(&["+HEAD:refs/heads/original"], None, None)
+            .unwrap();
+        let parent = fork.find_commit(original_commit).unwrap();
+        let fork_commit = commit(&fork, &[&parent]);
+
+        let source = |path: &Path| Source::Git {
+            url: Url::from_file_path(path).unwrap(),
+            reference: None,
+            options: GitOptions::default(),
+        };
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            source(&original_dir),
+            &HashSet::new(),
+            None,
+        )
+        .unwrap();
+        let previous = LockedExternalPlugin {
+            name: "test".into(),
+            source_dir: locked.dir,
+            commit: locked.commit.map(|c| c.to_string()),
+            tag: None,
+            sha256: None,
+            plugin_dir: None,
+            files: Vec::new(),
+            apply: Vec::new(),
+        };
+
+        // The commit locked for the original repository is not used for the
+        // fork.
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            source(&fork_dir),
+            &HashSet::new(),
+            Some(&previous),
+        )
+        .unwrap();
+        assert_eq!(locked.commit, Some(fork_commit));
+    }
+
     #[test]
     fn lock_with_git() {
         let temp = tempfile::tempdir().expect("create temporary directory");
@@ -135,14 +706,21 @@ mod tests {
         let source = Source::Git {
             url: Url::parse("https://github.com/rossmacarthur/sheldon-test").unwrap(),
             reference: None,
+            options: GitOptions::default(),
         };
-        let locked = lock(&ctx, source).unwrap();
+        let locked = lock(&ctx, LockMode::Normal, source, &HashSet::new(), None).unwrap();
 
         assert_eq!(
             locked,
             LockedSource {
                 dir: dir.join("repos/github.com/rossmacarthur/sheldon-test"),
                 file: None,
+                commit: Some(
+                    git2::Oid::from_str("be8fde277e76f35efbe46848fb352cee68549962").unwrap()
+                ),
+                tag: None,
+                sha256: None,
+                log: None,
             }
         );
     }
@@ -156,8 +734,10 @@ mod tests {
         let source = Source::Remote {
             url: Url::parse("https://github.com/rossmacarthur/sheldon/raw/0.3.0/LICENSE-MIT")
                 .unwrap(),
+            mirror: None,
+            sha256: None,
         };
-        let locked = lock(&ctx, source).unwrap();
+        let locked = lock(&ctx, LockMode::Normal, source, &HashSet::new(), None).unwrap();
 
         assert_eq!(
             locked.dir,
@@ -168,4 +748,104 @@ mod tests {
             Some(dir.join("downloads/github.com/rossmacarthur/sheldon/raw/0.3.0/LICENSE-MIT"))
         );
     }
+
+    #[test]
+    fn retry_until_success() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let mut ctx = Context::testing(temp.path());
+        ctx.network.retries = 2;
+        ctx.network.backoff = Duration::ZERO;
+
+        let mut calls = 0;
+        let result = retry(&ctx, "test", || {
+            calls += 1;
+            if calls < 3 {
+                bail!("attempt {calls} failed");
+            }
+            Ok(calls)
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let err = retry(&ctx, "test", || -> Result<()> {
+            calls += 1;
+            bail!("attempt {calls} failed")
+        })
+        .unwrap_err();
+        assert_eq!(calls, 3);
+        assert_eq!(
+            format!("{err:#}"),
+            "failed after 3 attempts: attempt 3 failed"
+        );
+    }
+
+    #[test]
+    fn request_options_from_hosts_and_netrc() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let mut ctx = Context::testing(temp.path());
+        ctx.home = temp.path().to_path_buf();
+        fs::write(
+            temp.path().join(".netrc"),
+            "machine github.com login octocat password ghp_token",
+        )
+        .unwrap();
+        let var = |name: &str| match name {
+            "GITLAB_TOKEN" => Some(OsString::from("glpat-token")),
+            _ => None,
+        };
+        ctx.network.hosts.insert(
+            "gitlab.corp.com".into(),
+            HostOptions {
+                username: None,
+                token_env: Some("GITLAB_TOKEN".into()),
+                headers: indexmap_into! { "X-Team" => "shell" },
+                ssh_key: Some("~/.ssh/id_deploy".into()),
+            },
+        );
+
+        let url = Url::parse("https://gitlab.corp.com/team/plugin.git").unwrap();
+        let request = request_options_with_env(&ctx, &url, var).unwrap();
+        assert_eq!(
+            request.credentials,
+            Some(("oauth2".into(), "glpat-token".into()))
+        );
+        assert_eq!(request.headers, ["X-Team: shell"]);
+        assert_eq!(
+            request.ssh_key,
+            Some(temp.path().join(".ssh").join("id_deploy"))
+        );
+        assert!(request.identity_files.is_empty());
+
+        let url = Url::parse("https://github.com/owner/repo").unwrap();
+        let request = request_options_with_env(&ctx, &url, var).unwrap();
+        assert_eq!(
+            request.credentials,
+            Some(("octocat".into(), "ghp_token".into()))
+        );
+        assert!(request.headers.is_empty());
+        assert_eq!(request.ssh_key, None);
+    }
+
+    #[test]
+    fn request_options_identity_files() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let mut ctx = Context::testing(temp.path());
+        ctx.home = temp.path().to_path_buf();
+        let ssh_dir = temp.path().join(".ssh");
+        fs::create_dir(&ssh_dir).unwrap();
+        fs::write(
+            ssh_dir.join("config"),
+            "Host github.com\n  IdentityFile ~/.ssh/id_github\n  IdentityFile ~/.ssh/id_missing\n",
+        )
+        .unwrap();
+        fs::write(ssh_dir.join("id_github"), "").unwrap();
+        fs::write(ssh_dir.join("id_ed25519"), "").unwrap();
+
+        let url = Url::parse("ssh://git@github.com/owner/repo").unwrap();
+        let request = request_options(&ctx, &url).unwrap();
+        assert_eq!(
+            request.identity_files,
+            [ssh_dir.join("id_github"), ssh_dir.join("id_ed25519")]
+        );
+    }
 }
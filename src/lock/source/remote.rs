@@ -1,52 +1,262 @@
+use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context as ResultExt, Result};
+use anyhow::{bail, Context as ResultExt, Result};
 use url::Url;
 
 use crate::context::Context;
-use crate::lock::source::LockedSource;
+use crate::lock::source::{check_online, request_options, retry, LockedSource};
 use crate::lock::LockMode;
 use crate::util;
-use crate::util::TempPath;
-
-pub fn lock(ctx: &Context, dir: PathBuf, file: PathBuf, url: &Url) -> Result<LockedSource> {
-    if matches!(ctx.lock_mode(), LockMode::Normal) && file.exists() {
-        ctx.log_status("Checked", url);
-        return Ok(LockedSource {
-            dir,
-            file: Some(file),
-        });
+use crate::util::{TempPath, Validators};
+
+/// Download a remote file.
+///
+/// If `sha256` is given then the downloaded file must match this checksum. The
+/// `previous` checksum is the one recorded in the lock file, and is used to
+/// report when the remote file has changed.
+pub fn lock(
+    ctx: &Context,
+    mode: LockMode,
+    dir: PathBuf,
+    file: PathBuf,
+    url: &Url,
+    sha256: Option<&str>,
+    previous: Option<&str>,
+) -> Result<LockedSource> {
+    if matches!(mode, LockMode::Frozen) && !file.exists() {
+        bail!("`{}` is not downloaded", file.display());
+    }
+
+    // In offline mode an existing file is always used.
+    let use_existing = matches!(mode, LockMode::Normal | LockMode::Frozen) || ctx.offline;
+    if use_existing && file.exists() {
+        let checksum = checksum(&file)?;
+        match sha256 {
+            Some(expected) if expected != checksum => {
+                if matches!(mode, LockMode::Frozen) {
+                    bail!(
+                        "checksum mismatch for `{}`: expected `{expected}`, got `{checksum}`",
+                        file.display()
+                    );
+                }
+            }
+            _ => {
+                ctx.log_status("Checked", url);
+                return Ok(LockedSource {
+                    dir,
+                    file: Some(file),
+                    commit: None,
+                    tag: None,
+                    sha256: Some(checksum),
+                    log: None,
+                });
+            }
+        }
     }
 
+    check_online(ctx, url)?;
+
+    // When updating, only download the file again if it changed.
+    let validators = match mode {
+        LockMode::Update if file.exists() => {
+            let current = checksum(&file)?;
+            if sha256.iter().all(|&expected| expected == current) {
+                read_validators(&file)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+
+    let checksum = match download_if_modified(ctx, &dir, &file, url, sha256, validators.as_ref())? {
+        Some((checksum, validators)) => {
+            write_validators(&file, &validators)?;
+            log_fetched(ctx, url, &checksum, previous);
+            checksum
+        }
+        None => {
+            ctx.log_status("Checked", &format!("{url} (not modified)"));
+            checksum(&file)?
+        }
+    };
+
+    Ok(LockedSource {
+        dir,
+        file: Some(file),
+        commit: None,
+        tag: None,
+        sha256: Some(checksum),
+        log: None,
+    })
+}
+
+/// Download a file to the given path, verifying it against the expected
+/// checksum, and returns the checksum of the downloaded file.
+pub fn download(
+    ctx: &Context,
+    dir: &Path,
+    file: &Path,
+    url: &Url,
+    sha256: Option<&str>,
+) -> Result<String> {
+    let (checksum, _) = download_if_modified(ctx, dir, file, url, sha256, None)?
+        .expect("file is always downloaded without validators");
+    Ok(checksum)
+}
+
+/// Download a file to the given path like [`download`], but if `validators`
+/// are given the file is only downloaded if it changed.
+///
+/// Returns `None` if the file was not modified, otherwise the checksum and the
+/// validators of the downloaded file.
+fn download_if_modified(
+    ctx: &Context,
+    dir: &Path,
+    file: &Path,
+    url: &Url,
+    sha256: Option<&str>,
+    validators: Option<&Validators>,
+) -> Result<Option<(String, Validators)>> {
     let temp_file =
-        TempPath::new_force(&file).context("failed to prepare temporary download directory")?;
-    {
+        TempPath::new_force(file).context("failed to prepare temporary download directory")?;
+    let received = {
         let path = temp_file.path();
-        fs::create_dir_all(&dir)
+        fs::create_dir_all(dir)
             .with_context(|| format!("failed to create dir `{}`", dir.display()))?;
-        let temp_file_handle = fs::File::create(path)
-            .with_context(|| format!("failed to create `{}`", path.display()))?;
-        util::download(url.as_ref(), temp_file_handle)
-            .with_context(|| format!("failed to download `{url}`"))?;
+        let request = request_options(ctx, url)?;
+        retry(ctx, url, || {
+            let temp_file_handle = fs::File::create(path)
+                .with_context(|| format!("failed to create `{}`", path.display()))?;
+            util::download(url.as_ref(), temp_file_handle, &request, validators)
+                .with_context(|| format!("failed to download `{url}`"))
+        })?
+    };
+    let received = match received {
+        Some(received) => received,
+        None => return Ok(None),
+    };
+    let checksum = checksum(temp_file.path())?;
+    if let Some(expected) = sha256 {
+        if expected != checksum {
+            bail!("checksum mismatch for `{url}`: expected `{expected}`, got `{checksum}`");
+        }
     }
     temp_file
-        .rename(&file)
+        .rename(file)
         .context("failed to rename temporary download file")?;
-    ctx.log_status("Fetched", url);
+    Ok(Some((checksum, received)))
+}
 
-    Ok(LockedSource {
-        dir,
-        file: Some(file),
-    })
+/// The path that the validators of a downloaded file are stored at.
+pub fn validators_file(file: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(file.file_name().unwrap());
+    name.push(".headers");
+    file.with_file_name(name)
+}
+
+/// Read the stored validators of a downloaded file, if there are any.
+fn read_validators(file: &Path) -> Option<Validators> {
+    let contents = fs::read(validators_file(file)).ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+/// Store the validators of a downloaded file, so that it is only downloaded
+/// again if it changed.
+fn write_validators(file: &Path, validators: &Validators) -> Result<()> {
+    let path = validators_file(file);
+    if validators.is_empty() {
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("failed to remove `{}`", path.display()))
+            }
+            _ => Ok(()),
+        }
+    } else {
+        let contents = serde_json::to_vec(validators)?;
+        fs::write(&path, contents).with_context(|| format!("failed to write `{}`", path.display()))
+    }
+}
+
+/// Log that a file was downloaded, reporting if it changed from the `previous`
+/// checksum.
+pub fn log_fetched(ctx: &Context, url: &Url, checksum: &str, previous: Option<&str>) {
+    match previous {
+        Some(previous) if previous != checksum => ctx.log_status(
+            "Updated",
+            &format!("{url} ({} to {})", short(previous), short(checksum)),
+        ),
+        _ => ctx.log_status("Fetched", url),
+    }
+}
+
+pub fn checksum(path: &Path) -> Result<String> {
+    util::sha256(path).with_context(|| format!("failed to read `{}`", path.display()))
+}
+
+pub fn short(checksum: &str) -> &str {
+    checksum.get(..12).unwrap_or(checksum)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
     use std::thread;
     use std::time;
 
     use super::*;
+    use crate::util::test_server::{self, Server};
+
+    /// A local HTTP server serving a single file with an `ETag`, that responds
+    /// with "304 Not Modified" when the `If-None-Match` header matches.
+    struct FileServer {
+        url: Url,
+        /// The current `ETag` and contents of the file.
+        file: Arc<Mutex<(String, String)>>,
+        /// The status of each response.
+        statuses: Arc<Mutex<Vec<u16>>>,
+    }
+
+    impl FileServer {
+        fn new(etag: &str, contents: &str) -> Self {
+            let file = Arc::new(Mutex::new((etag.to_string(), contents.to_string())));
+            let statuses = Arc::new(Mutex::new(Vec::new()));
+            let (served, recorded) = (file.clone(), statuses.clone());
+            let server = Server::new(move |request, stream| {
+                let (etag, contents) = served.lock().unwrap().clone();
+                let (status, body) = if request.header("If-None-Match") == Some(&etag) {
+                    (304, "")
+                } else {
+                    (200, contents.as_str())
+                };
+                let reason = if status == 304 { "Not Modified" } else { "OK" };
+                test_server::respond(
+                    stream,
+                    &format!("{status} {reason}"),
+                    &[("ETag", &etag)],
+                    body.as_bytes(),
+                );
+                recorded.lock().unwrap().push(status);
+            });
+            Self {
+                url: server.url("/test.plugin.zsh"),
+                file,
+                statuses,
+            }
+        }
+
+        fn set(&self, etag: &str, contents: &str) {
+            *self.file.lock().unwrap() = (etag.to_string(), contents.to_string());
+        }
+
+        fn take_statuses(&self) -> Vec<u16> {
+            std::mem::take(&mut *self.statuses.lock().unwrap())
+        }
+    }
 
     #[test]
     fn lock_remote_and_reinstall() {
@@ -54,11 +264,20 @@ mod tests {
         let temp = tempfile::tempdir().expect("create temporary directory");
         let dir = temp.path();
         let file = dir.join("test.txt");
-        let mut ctx = Context::testing(dir);
+        let ctx = Context::testing(dir);
         let url =
             Url::parse("https://github.com/rossmacarthur/sheldon/raw/0.3.0/LICENSE-MIT").unwrap();
 
-        let locked = lock(&ctx, dir.to_path_buf(), file.clone(), &url).unwrap();
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.to_path_buf(),
+            file.clone(),
+            &url,
+            None,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(locked.dir, dir);
         assert_eq!(locked.file, Some(file.clone()));
@@ -69,8 +288,16 @@ mod tests {
 
         let modified = fs::metadata(&file).unwrap().modified().unwrap();
         thread::sleep(time::Duration::from_secs(1));
-        ctx.lock_mode = Some(LockMode::Reinstall);
-        let locked = lock(&ctx, dir.to_path_buf(), file.clone(), &url).unwrap();
+        let locked = lock(
+            &ctx,
+            LockMode::Reinstall,
+            dir.to_path_buf(),
+            file.clone(),
+            &url,
+            None,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(locked.dir, dir);
         assert_eq!(locked.file, Some(file.clone()));
@@ -80,4 +307,126 @@ mod tests {
         );
         assert!(fs::metadata(&file).unwrap().modified().unwrap() > modified)
     }
+
+    #[test]
+    fn lock_remote_with_sha256() {
+        let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let dir = temp.path();
+        let file = dir.join("test.txt");
+        let ctx = Context::testing(dir);
+        let url =
+            Url::parse("https://github.com/rossmacarthur/sheldon/raw/0.3.0/LICENSE-MIT").unwrap();
+        let sha256 = util::sha256(&manifest_dir.join("LICENSE-MIT")).unwrap();
+
+        let err = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.to_path_buf(),
+            file.clone(),
+            &url,
+            Some("0".repeat(64).as_str()),
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("checksum mismatch for"));
+        assert!(!file.exists());
+
+        let locked = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.to_path_buf(),
+            file.clone(),
+            &url,
+            Some(&sha256),
+            None,
+        )
+        .unwrap();
+        assert_eq!(locked.file, Some(file));
+        assert_eq!(locked.sha256, Some(sha256));
+    }
+
+    #[test]
+    fn lock_remote_offline() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let dir = temp.path();
+        let file = dir.join("test.txt");
+        let mut ctx = Context::testing(dir);
+        ctx.offline = true;
+        let url = Url::parse("https://example.invalid/test.txt").unwrap();
+
+        let err = lock(
+            &ctx,
+            LockMode::Normal,
+            dir.to_path_buf(),
+            file.clone(),
+            &url,
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`https://example.invalid/test.txt` is not available on disk and cannot be fetched in \
+             offline mode"
+        );
+
+        fs::write(&file, "test").unwrap();
+        let locked = lock(
+            &ctx,
+            LockMode::Update,
+            dir.to_path_buf(),
+            file.clone(),
+            &url,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(locked.file, Some(file));
+        assert_eq!(
+            locked.sha256,
+            Some(util::sha256(&dir.join("test.txt")).unwrap())
+        );
+    }
+
+    #[test]
+    fn lock_remote_update_not_modified() {
+        let temp = tempfile::tempdir().expect("create temporary directory");
+        let dir = temp.path();
+        let file = dir.join("test.plugin.zsh");
+        let ctx = Context::testing(dir);
+        let server = FileServer::new("\"v1\"", "echo v1");
+        let lock = |mode| {
+            lock(
+                &ctx,
+                mode,
+                dir.to_path_buf(),
+                file.clone(),
+                &server.url,
+                None,
+                None,
+            )
+        };
+
+        lock(LockMode::Normal).unwrap();
+        assert_eq!(server.take_statuses(), [200]);
+        assert_eq!(
+            read_validators(&file),
+            Some(Validators {
+                etag: Some("\"v1\"".into()),
+                last_modified: None,
+            })
+        );
+
+        let locked = lock(LockMode::Update).unwrap();
+        assert_eq!(server.take_statuses(), [304]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "echo v1");
+        assert_eq!(locked.sha256, Some(checksum(&file).unwrap()));
+
+        server.set("\"v2\"", "echo v2");
+        lock(LockMode::Update).unwrap();
+        assert_eq!(server.take_statuses(), [200]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "echo v2");
+        assert_eq!(read_validators(&file).unwrap().etag, Some("\"v2\"".into()));
+    }
 }
@@ -9,6 +9,7 @@ mod util;
 
 use std::fs;
 use std::io;
+use std::iter;
 use std::panic;
 use std::path::Path;
 use std::process;
@@ -18,7 +19,7 @@ use anyhow::{bail, Context as ResultExt, Error, Result};
 use crate::cli::{Command, Opt};
 use crate::config::{EditConfig, EditPlugin, Shell};
 use crate::context::Context;
-use crate::lock::LockedConfig;
+use crate::lock::{LockMode, LockedConfig, LogFormat};
 use crate::util::underlying_io_error_kind;
 
 fn main() {
@@ -44,7 +45,14 @@ pub fn run_command(ctx: &Context, command: Command) -> Result<()> {
     // the lock and source commands.
     let _guard = match acquire_mutex(ctx, ctx.config_dir()) {
         Ok(g) => Some(g),
-        Err(_) if !matches!(command, Command::Lock | Command::Source) => None,
+        Err(_)
+            if !matches!(
+                command,
+                Command::Lock | Command::Source | Command::Outdated | Command::Rollback { .. }
+            ) =>
+        {
+            None
+        }
         Err(err) => {
             return Err(err).context("failed to acquire lock on config directory");
         }
@@ -57,6 +65,8 @@ pub fn run_command(ctx: &Context, command: Command) -> Result<()> {
         Command::Remove { name } => remove(ctx, name),
         Command::Lock => lock(ctx, &mut warnings),
         Command::Source => source(ctx, &mut warnings),
+        Command::Outdated => outdated(ctx, &mut warnings),
+        Command::Rollback { to } => rollback(ctx, to, &mut warnings),
     };
     for err in &warnings {
         ctx.log_error_as_warning(err);
@@ -196,10 +206,37 @@ fn lock(ctx: &Context, warnings: &mut Vec<Error>) -> Result<()> {
             ctx.log_error(&err);
         }
         Err(last)
+    } else if ctx.lock_mode() == LockMode::Frozen {
+        let path = ctx.lock_file();
+        let diffs = lock::from_path(ctx, path)?.diff(&locked);
+        if !diffs.is_empty() {
+            bail!(
+                "lock file is out of date\n{}",
+                diffs
+                    .iter()
+                    .map(|diff| format!("\n  - {diff}"))
+                    .collect::<String>()
+            );
+        }
+        ctx.log_header("Verified", path);
+        Ok(())
     } else {
         let path = ctx.lock_file();
         locked.to_path(path).context("failed to write lock file")?;
         ctx.log_header("Locked", path);
+        match ctx.update_log {
+            Some(LogFormat::Text) => {
+                for update in &locked.updates {
+                    print!("{update}");
+                }
+            }
+            Some(LogFormat::Json) => {
+                let json = serde_json::to_string_pretty(&locked.updates)
+                    .context("failed to serialize updates")?;
+                println!("{json}");
+            }
+            None => {}
+        }
         Ok(())
     }
 }
@@ -216,7 +253,7 @@ fn source(ctx: &Context, warnings: &mut Vec<Error>) -> Result<()> {
     let locked_config = if ctx.lock_mode.is_some() || newer_than(config_path, lock_path) {
         locked(ctx, warnings)?
     } else {
-        match lock::from_path(lock_path) {
+        match lock::from_path(ctx, lock_path) {
             Ok(locked_config) => {
                 if locked_config.verify(ctx) {
                     to_path = false;
@@ -226,6 +263,7 @@ fn source(ctx: &Context, warnings: &mut Vec<Error>) -> Result<()> {
                     locked(ctx, warnings)?
                 }
             }
+            Err(err) if err.is::<lock::NewerSchemaError>() => return Err(err),
             Err(_) => locked(ctx, warnings)?,
         }
     };
@@ -249,6 +287,77 @@ fn source(ctx: &Context, warnings: &mut Vec<Error>) -> Result<()> {
     Ok(())
 }
 
+/// Execute the `outdated` subcommand.
+///
+/// Show plugins that are behind upstream.
+fn outdated(ctx: &Context, warnings: &mut Vec<Error>) -> Result<()> {
+    let path = ctx.config_file();
+    let config = config::from_path(path, warnings).context("failed to load config file")?;
+    ctx.log_header("Loaded", path);
+    let mut outdated = lock::outdated(ctx, config);
+
+    let short = |oid: git2::Oid| oid.to_string()[..7].to_string();
+    let rows: Vec<_> = outdated
+        .plugins
+        .iter()
+        .filter(|(_, o)| o.behind > 0 || !o.tags.is_empty())
+        .map(|(name, o)| {
+            [
+                name.clone(),
+                short(o.current),
+                short(o.available),
+                o.behind.to_string(),
+                o.tags.join(", "),
+            ]
+        })
+        .collect();
+
+    if rows.is_empty() {
+        ctx.log_header("Checked", &"all plugins are up to date");
+    } else {
+        let header = ["NAME", "CURRENT", "AVAILABLE", "BEHIND", "NEWER TAGS"].map(String::from);
+        let mut widths = [0; 5];
+        for row in iter::once(&header).chain(&rows) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        for row in iter::once(&header).chain(&rows) {
+            let line: String = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:width$}  "))
+                .collect();
+            println!("{}", line.trim_end());
+        }
+    }
+
+    if let Some(last) = outdated.errors.pop() {
+        for err in outdated.errors {
+            ctx.log_error(&err);
+        }
+        Err(last)
+    } else {
+        Ok(())
+    }
+}
+
+/// Execute the `rollback` subcommand.
+///
+/// Restore a previous lock file and check out its plugin sources.
+fn rollback(ctx: &Context, to: usize, warnings: &mut Vec<Error>) -> Result<()> {
+    let path = ctx.lock_file();
+    let timestamp = lock::rollback(path, to).context("failed to restore lock file")?;
+    ctx.log_header(
+        "Restored",
+        &format!(
+            "{} (replaced at Unix time {timestamp})",
+            ctx.replace_home(path).display()
+        ),
+    );
+    lock(ctx, warnings)
+}
+
 /// Returns `true` if the left path is newer than the right.
 fn newer_than(left: &Path, right: &Path) -> bool {
     let modified = |p| fs::metadata(p).and_then(|m| m.modified()).ok();
@@ -264,6 +373,8 @@ fn locked(ctx: &Context, warnings: &mut Vec<Error>) -> Result<LockedConfig> {
     let path = ctx.config_file();
     let config = config::from_path(path, warnings).context("failed to load config file")?;
     ctx.log_header("Loaded", path);
-    config::clean(ctx, warnings, &config)?;
+    if ctx.lock_mode() != LockMode::Frozen {
+        config::clean(ctx, warnings, &config)?;
+    }
     lock::config(ctx, config)
 }
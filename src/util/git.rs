@@ -1,27 +1,61 @@
 //! Git helpers.
 
-use std::path::Path;
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+use std::time::Instant;
+use std::{env, fs, mem};
 
 use git2::{
     BranchType, Cred, CredentialType, Error, FetchOptions, Oid, RemoteCallbacks, Repository,
     ResetType,
 };
 use once_cell::sync::Lazy;
+use semver::{Version, VersionReq};
 use url::Url;
 
-use anyhow::Context as ResultExt;
+use anyhow::{bail, Context as ResultExt};
+
+use crate::util::RequestOptions;
 
 /// Call a function with generated fetch options.
-fn with_fetch_options<T, F>(f: F) -> anyhow::Result<T>
+fn with_fetch_options<T, F>(request: &RequestOptions, f: F) -> anyhow::Result<T>
 where
     F: FnOnce(FetchOptions<'_>) -> anyhow::Result<T>,
 {
     let mut rcb = RemoteCallbacks::new();
-    rcb.credentials(|_, username, allowed| {
+    // libgit2 asks again if the credentials are rejected, so each kind of
+    // credential is only offered once.
+    let mut credentials = request.credentials.as_ref();
+    let mut try_agent = cfg!(windows) || env::var_os("SSH_AUTH_SOCK").is_some();
+    let mut ssh_keys = request.ssh_key.iter().chain(&request.identity_files);
+    let mut try_helper = true;
+    rcb.credentials(move |url, username, allowed| {
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username.unwrap_or("git"));
+        }
         if allowed.contains(CredentialType::SSH_KEY) {
-            if let Some(username) = username {
+            let username = username.unwrap_or("git");
+            if mem::take(&mut try_agent) {
                 return Cred::ssh_key_from_agent(username);
             }
+            if let Some(key) = ssh_keys.next() {
+                return Cred::ssh_key(username, None, key, None);
+            }
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if let Some((username, password)) = credentials.take() {
+                return Cred::userpass_plaintext(username, password);
+            }
+            // Fall back to the user's configured `git credential` helper.
+            if mem::take(&mut try_helper) {
+                if let Ok(config) = git2::Config::open_default() {
+                    if let Ok(cred) = Cred::credential_helper(&config, url, username) {
+                        return Ok(cred);
+                    }
+                }
+            }
         }
         if allowed.contains(CredentialType::DEFAULT) {
             return Cred::default();
@@ -31,15 +65,44 @@ where
         ))
     });
 
+    // libgit2 doesn't support timeouts, so abort the transfer if it makes no
+    // progress for longer than the timeout. The callback is only called when
+    // data is received, so this can't abort a transfer that receives nothing.
+    let timeout = request.timeout;
+    let stalled = Rc::new(Cell::new(false));
+    let is_stalled = Rc::clone(&stalled);
+    let mut last = (Instant::now(), 0);
+    rcb.transfer_progress(move |progress| {
+        let received = progress.received_bytes();
+        if received != last.1 {
+            let now = Instant::now();
+            if now.duration_since(last.0) > timeout {
+                is_stalled.set(true);
+                return false;
+            }
+            last = (now, received);
+        }
+        true
+    });
+
     // Try to auto-detect the proxy from the git configuration so that
     // Sheldon can be used behind a proxy.
     let mut proxy_opts = git2::ProxyOptions::new();
     proxy_opts.auto();
 
+    let headers: Vec<&str> = request.headers.iter().map(String::as_str).collect();
     let mut opts = FetchOptions::new();
     opts.remote_callbacks(rcb);
     opts.proxy_options(proxy_opts);
-    f(opts)
+    opts.custom_headers(&headers);
+    let result = f(opts);
+    if stalled.get() {
+        bail!(
+            "the transfer stalled for longer than {}s",
+            timeout.as_secs()
+        );
+    }
+    result
 }
 
 /// Open a Git repository.
@@ -49,7 +112,7 @@ pub fn open(dir: &Path) -> anyhow::Result<Repository> {
     Ok(repo)
 }
 
-static DEFAULT_REFSPECS: Lazy<Vec<String>> = Lazy::new(|| {
+pub static DEFAULT_REFSPECS: Lazy<Vec<String>> = Lazy::new(|| {
     vec_into![
         "+refs/heads/*:refs/remotes/origin/*",
         "+HEAD:refs/remotes/origin/HEAD"
@@ -57,8 +120,8 @@ static DEFAULT_REFSPECS: Lazy<Vec<String>> = Lazy::new(|| {
 });
 
 /// Clone a Git repository.
-pub fn clone(url: &Url, dir: &Path) -> anyhow::Result<Repository> {
-    with_fetch_options(|mut opts| {
+pub fn clone(url: &Url, dir: &Path, request: &RequestOptions) -> anyhow::Result<Repository> {
+    with_fetch_options(request, |mut opts| {
         let repo = Repository::init(dir)?;
         repo.remote("origin", url.as_str())?
             .fetch(&DEFAULT_REFSPECS, Some(&mut opts), None)?;
@@ -68,8 +131,8 @@ pub fn clone(url: &Url, dir: &Path) -> anyhow::Result<Repository> {
 }
 
 /// Fetch a Git repository.
-pub fn fetch(repo: &Repository) -> anyhow::Result<()> {
-    with_fetch_options(|mut opts| {
+pub fn fetch(repo: &Repository, request: &RequestOptions) -> anyhow::Result<()> {
+    with_fetch_options(request, |mut opts| {
         repo.find_remote("origin")
             .context("failed to find remote `origin`")?
             .fetch(&DEFAULT_REFSPECS, Some(&mut opts), None)?;
@@ -78,11 +141,241 @@ pub fn fetch(repo: &Repository) -> anyhow::Result<()> {
     .context("failed to git fetch")
 }
 
+/// Fetch a Git repository from the given URL instead of its `origin` remote,
+/// without changing the remote.
+///
+/// The remote-tracking refs of `origin` are updated as if it was fetched.
+pub fn fetch_url(repo: &Repository, url: &Url, request: &RequestOptions) -> anyhow::Result<()> {
+    with_fetch_options(request, |mut opts| {
+        repo.remote_anonymous(url.as_str())?
+            .fetch(&DEFAULT_REFSPECS, Some(&mut opts), None)?;
+        Ok(())
+    })
+    .with_context(|| format!("failed to git fetch `{url}`"))
+}
+
+/// Get the URL of the `origin` remote of a Git repository.
+pub fn origin_url(repo: &Repository) -> anyhow::Result<String> {
+    let remote = repo
+        .find_remote("origin")
+        .context("failed to find remote `origin`")?;
+    Ok(remote.url().unwrap_or_default().to_string())
+}
+
+/// Change the URL of the `origin` remote of a Git repository.
+pub fn set_origin_url(repo: &Repository, url: &Url) -> anyhow::Result<()> {
+    repo.remote_set_url("origin", url.as_str())
+        .with_context(|| format!("failed to set the URL of remote `origin` to `{url}`"))
+}
+
+/// Clone a Git repository, only fetching the given refspecs.
+///
+/// See [`fetch_shallow`].
+pub fn clone_shallow(
+    url: &Url,
+    dir: &Path,
+    refspecs: &[String],
+    depth: Option<u32>,
+    filter: Option<&str>,
+    request: &RequestOptions,
+) -> anyhow::Result<Repository> {
+    let repo = Repository::init(dir)
+        .and_then(|repo| {
+            repo.remote("origin", url.as_str())?;
+            Ok(repo)
+        })
+        .with_context(|| format!("failed to git clone `{url}`"))?;
+    fetch_shallow(&repo, "origin", refspecs, depth, filter, request)
+        .with_context(|| format!("failed to git clone `{url}`"))?;
+    Ok(repo)
+}
+
+/// Fetch the given refspecs of a Git repository from a remote, which is either
+/// the name of a remote or a URL, with at most `depth` commits of history and
+/// only the objects matching `filter`.
+///
+/// libgit2 doesn't support shallow or partial clones so this uses the `git`
+/// command line tool.
+pub fn fetch_shallow(
+    repo: &Repository,
+    remote: &str,
+    refspecs: &[String],
+    depth: Option<u32>,
+    filter: Option<&str>,
+    request: &RequestOptions,
+) -> anyhow::Result<()> {
+    let mut args: Vec<String> = vec_into!["fetch", "--quiet"];
+    if let Some(depth) = depth {
+        args.push(format!("--depth={depth}"));
+    }
+    if let Some(filter) = filter {
+        args.push(format!("--filter={filter}"));
+    }
+    args.push(remote.to_string());
+    args.extend(refspecs.iter().cloned());
+    run_with_config(repo, &args, &request_config(request)).context("failed to git fetch")
+}
+
+/// Fetch the complete history of a shallow Git repository.
+pub fn unshallow(
+    repo: &Repository,
+    refspecs: &[String],
+    request: &RequestOptions,
+) -> anyhow::Result<()> {
+    let mut args: Vec<String> = vec_into!["fetch", "--quiet", "--unshallow", "origin"];
+    args.extend(refspecs.iter().cloned());
+    run_with_config(repo, &args, &request_config(request)).context("failed to git fetch")
+}
+
+/// The `git` configuration to use the request options when fetching.
+fn request_config(request: &RequestOptions) -> Vec<(String, String)> {
+    // Make a transfer fail when it stalls for longer than the timeout.
+    let mut config = vec![
+        ("http.lowSpeedLimit".into(), "1".into()),
+        (
+            "http.lowSpeedTime".into(),
+            request.timeout.as_secs().max(1).to_string(),
+        ),
+    ];
+    let auth = request.credentials.as_ref().map(|(username, password)| {
+        let encoded = base64::encode(format!("{username}:{password}"));
+        format!("Authorization: Basic {encoded}")
+    });
+    for header in request.headers.iter().cloned().chain(auth) {
+        config.push(("http.extraHeader".into(), header));
+    }
+    if let Some(key) = &request.ssh_key {
+        // The key is quoted for the shell that Git runs the command with.
+        let key = key.to_string_lossy().replace('\'', r"'\''");
+        config.push(("core.sshCommand".into(), format!("ssh -i '{key}'")));
+    }
+    config
+}
+
+/// Whether a Git repository is a partial clone, i.e. objects are fetched from
+/// the remote when they are needed.
+pub fn is_partial(repo: &Repository) -> bool {
+    config_bool(repo, "remote.origin.promisor")
+}
+
+/// Limit the working tree of a Git repository to the given paths, or restore
+/// the complete working tree if `paths` is `None`.
+///
+/// libgit2 doesn't support sparse checkouts so the working tree is updated
+/// using the `git` command line tool. Nothing is done if the repository
+/// already has these paths.
+pub fn sparse_checkout(repo: &Repository, paths: Option<&[String]>) -> anyhow::Result<()> {
+    let file = repo.path().join("info").join("sparse-checkout");
+    let patterns: Option<Vec<String>> =
+        paths.map(|paths| paths.iter().map(|path| format!("/{path}")).collect());
+    let current: Option<Vec<String>> = if is_sparse(repo) {
+        fs::read_to_string(&file)
+            .ok()
+            .map(|contents| contents.lines().map(str::to_string).collect())
+    } else {
+        None
+    };
+    if current == patterns {
+        return Ok(());
+    }
+
+    let has_head = repo.head().is_ok();
+    let update: Vec<String> = vec_into!["read-tree", "-mu", "HEAD"];
+    let mut config = git2::Config::open(&repo.path().join("config"))?;
+    let write = |contents: String| {
+        fs::create_dir_all(file.parent().unwrap())
+            .and_then(|_| fs::write(&file, contents))
+            .with_context(|| format!("failed to write `{}`", file.display()))
+    };
+    match patterns {
+        Some(patterns) => {
+            write(patterns.join("\n") + "\n")?;
+            config.set_bool("core.sparseCheckout", true)?;
+            if has_head {
+                run(repo, &update).context("failed to update sparse checkout")?;
+            }
+        }
+        None => {
+            // Check out everything before disabling the sparse checkout,
+            // otherwise the missing files are still marked as skipped.
+            write("/*\n".to_string())?;
+            if has_head {
+                run(repo, &update).context("failed to update sparse checkout")?;
+            }
+            config.set_bool("core.sparseCheckout", false)?;
+            fs::remove_file(&file)
+                .with_context(|| format!("failed to remove `{}`", file.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a Git repository only checks out some paths.
+fn is_sparse(repo: &Repository) -> bool {
+    config_bool(repo, "core.sparseCheckout")
+}
+
+/// Read a boolean from the repository's own config file.
+///
+/// The file is read again each time because the `git` command line tool may
+/// have changed it since libgit2 loaded it.
+fn config_bool(repo: &Repository, name: &str) -> bool {
+    git2::Config::open(&repo.path().join("config"))
+        .and_then(|config| config.get_bool(name))
+        .unwrap_or(false)
+}
+
+/// Run a `git` command in the working directory of a repository.
+fn run(repo: &Repository, args: &[String]) -> anyhow::Result<()> {
+    run_with_config(repo, args, &[])
+}
+
+/// Run a `git` command in the working directory of a repository, with extra
+/// configuration.
+///
+/// The configuration is passed using environment variables so that it is not
+/// visible in the arguments of the process.
+fn run_with_config(
+    repo: &Repository,
+    args: &[String],
+    config: &[(String, String)],
+) -> anyhow::Result<()> {
+    let dir = repo.workdir().unwrap_or_else(|| repo.path());
+    let mut cmd = Command::new("git");
+    if !config.is_empty() {
+        cmd.env("GIT_CONFIG_COUNT", config.len().to_string());
+        for (i, (key, value)) in config.iter().enumerate() {
+            cmd.env(format!("GIT_CONFIG_KEY_{i}"), key);
+            cmd.env(format!("GIT_CONFIG_VALUE_{i}"), value);
+        }
+    }
+    let output = cmd
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("failed to run `git`, is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
 /// Checkout at repository at a particular revision.
 pub fn checkout(repo: &Repository, oid: Oid) -> anyhow::Result<()> {
     let obj = repo
         .find_object(oid, None)
         .with_context(|| format!("failed to find `{oid}`"))?;
+    // libgit2 can't fetch the missing objects of a partial clone, and doesn't
+    // respect sparse checkouts.
+    if is_partial(repo) || is_sparse(repo) {
+        let args: Vec<String> = vec_into!["reset", "--quiet", "--hard", oid.to_string()];
+        return run(repo, &args).with_context(|| format!("failed to checkout `{oid}`"));
+    }
     repo.reset(&obj, ResetType::Hard, None)
         .with_context(|| format!("failed to set HEAD to `{oid}`"))?;
     repo.checkout_tree(&obj, None)
@@ -90,20 +383,41 @@ pub fn checkout(repo: &Repository, oid: Oid) -> anyhow::Result<()> {
 }
 
 /// Recursively update Git submodules.
-pub fn submodule_update(repo: &Repository) -> Result<(), Error> {
-    fn _submodule_update(repo: &Repository, todo: &mut Vec<Repository>) -> Result<(), Error> {
+///
+/// If `paths` is given then only the submodules of the repository at these
+/// paths are updated, along with all of their own submodules. Returns the
+/// paths of the updated submodules.
+pub fn submodule_update(
+    repo: &Repository,
+    paths: Option<&[String]>,
+) -> Result<Vec<PathBuf>, Error> {
+    fn _submodule_update(
+        repo: &Repository,
+        prefix: &Path,
+        paths: Option<&[String]>,
+        todo: &mut Vec<(PathBuf, Repository)>,
+        updated: &mut Vec<PathBuf>,
+    ) -> Result<(), Error> {
         for mut submodule in repo.submodules()? {
+            if let Some(paths) = paths {
+                if !paths.iter().any(|p| Path::new(p) == submodule.path()) {
+                    continue;
+                }
+            }
             submodule.update(true, None)?;
-            todo.push(submodule.open()?);
+            let path = prefix.join(submodule.path());
+            todo.push((path.clone(), submodule.open()?));
+            updated.push(path);
         }
         Ok(())
     }
     let mut repos = Vec::new();
-    _submodule_update(repo, &mut repos)?;
-    while let Some(repo) = repos.pop() {
-        _submodule_update(&repo, &mut repos)?;
+    let mut updated = Vec::new();
+    _submodule_update(repo, Path::new(""), paths, &mut repos, &mut updated)?;
+    while let Some((prefix, repo)) = repos.pop() {
+        _submodule_update(&repo, &prefix, None, &mut repos, &mut updated)?;
     }
-    Ok(())
+    Ok(updated)
 }
 
 fn resolve_refname(repo: &Repository, refname: &str) -> Result<Oid, Error> {
@@ -148,3 +462,56 @@ pub fn resolve_tag(repo: &Repository, tag: &str) -> anyhow::Result<Oid> {
     }
     _resolve_tag(repo, tag).with_context(|| format!("failed to find tag `{tag}`"))
 }
+
+/// Returns the tags that match a semantic version requirement, highest version
+/// first.
+///
+/// A leading `v` in tag names is ignored, tags that are not semantic versions
+/// are skipped.
+pub fn resolve_version(repo: &Repository, req: &str) -> anyhow::Result<Vec<(String, Oid)>> {
+    let req = VersionReq::parse(req)
+        .with_context(|| format!("failed to parse version requirement `{req}`"))?;
+    let mut tags = Vec::new();
+    for name in repo.tag_names(None)?.iter().flatten() {
+        if let Some(version) = tag_version(name) {
+            if req.matches(&version) {
+                tags.push((version, name.to_string()));
+            }
+        }
+    }
+    tags.sort_by(|a, b| b.0.cmp(&a.0));
+    tags.into_iter()
+        .map(|(_, name)| {
+            let oid = resolve_tag(repo, &name)?;
+            Ok((name, oid))
+        })
+        .collect()
+}
+
+/// Parse the semantic version of a tag name, ignoring a leading `v`.
+pub fn tag_version(name: &str) -> Option<Version> {
+    Version::parse(name.strip_prefix('v').unwrap_or(name)).ok()
+}
+
+/// Returns the tags that point to descendants of the given tag, oldest first.
+///
+/// Semantic version tags are ordered by version, like [`resolve_version`], and
+/// come before any other tags, which are ordered by commit time. Tags that
+/// don't point to a commit are skipped.
+pub fn newer_tags(repo: &Repository, tag: &str) -> anyhow::Result<Vec<String>> {
+    let oid = resolve_tag(repo, tag)?;
+    let mut tags = Vec::new();
+    for name in repo.tag_names(None)?.iter().flatten() {
+        let id = match resolve_tag(repo, name) {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        if repo.graph_descendant_of(id, oid)? {
+            let version = tag_version(name);
+            let time = repo.find_commit(id)?.time().seconds();
+            tags.push((version.is_none(), version, time, name.to_string()));
+        }
+    }
+    tags.sort();
+    Ok(tags.into_iter().map(|(_, _, _, name)| name).collect())
+}
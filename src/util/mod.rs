@@ -1,15 +1,23 @@
 pub mod build;
 pub mod git;
+pub mod netrc;
 mod path_ext;
+pub mod ssh;
 mod temp;
+#[cfg(test)]
+pub mod test_server;
 
 use std::fs::File;
 use std::io;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::result;
+use std::time::Duration;
 
 use anyhow::Error;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 pub use crate::util::path_ext::PathExt;
 pub use crate::util::temp::TempPath;
@@ -26,19 +34,179 @@ pub fn underlying_io_error_kind(error: &Error) -> Option<io::ErrorKind> {
     None
 }
 
+/// Options for requests to a remote host.
+#[derive(Debug, Clone)]
+pub struct RequestOptions {
+    /// How long to wait to connect, or for more data when a transfer stalls.
+    ///
+    /// Fetches that use libgit2 only honor this once data arrives after a
+    /// stall, they can still hang while connecting or when nothing arrives.
+    pub timeout: Duration,
+    /// The username and password to authenticate with.
+    pub credentials: Option<(String, String)>,
+    /// Extra headers to send, formatted as `Name: value`.
+    pub headers: Vec<String>,
+    /// The SSH private key configured for the host.
+    pub ssh_key: Option<PathBuf>,
+    /// Other SSH private keys to try when there is no configured key or agent.
+    pub identity_files: Vec<PathBuf>,
+}
+
+/// Configure a cURL handle with the request options and headers.
+fn configure(
+    easy: &mut curl::easy::Easy,
+    opts: &RequestOptions,
+    mut headers: curl::easy::List,
+) -> result::Result<(), curl::Error> {
+    // Give up when connecting, or when a transfer stalls, for longer than the
+    // timeout.
+    easy.connect_timeout(opts.timeout)?; // --connect-timeout
+    easy.low_speed_limit(1)?; // -Y
+    easy.low_speed_time(opts.timeout)?; // -y
+    if let Some((username, password)) = &opts.credentials {
+        easy.username(username)?; // -u
+        easy.password(password)?;
+    }
+    for header in &opts.headers {
+        headers.append(header)?; // -H
+    }
+    easy.http_headers(headers)
+}
+
+/// The HTTP response headers that identify a version of a downloaded file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Validators {
+    /// The value of the `ETag` header.
+    pub etag: Option<String>,
+    /// The value of the `Last-Modified` header.
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// Whether the server didn't send any validators.
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
 /// Download a remote file.
-pub fn download(url: &str, mut file: File) -> result::Result<(), curl::Error> {
+///
+/// If `validators` are given then a conditional request is made, and `None`
+/// is returned if the remote file has not changed. Otherwise the validators of
+/// the downloaded file are returned.
+pub fn download(
+    url: &str,
+    mut file: File,
+    opts: &RequestOptions,
+    validators: Option<&Validators>,
+) -> result::Result<Option<Validators>, curl::Error> {
+    let mut headers = curl::easy::List::new();
+    if let Some(validators) = validators {
+        if let Some(etag) = &validators.etag {
+            headers.append(&format!("If-None-Match: {etag}"))?;
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            headers.append(&format!("If-Modified-Since: {last_modified}"))?;
+        }
+    }
     let mut easy = curl::easy::Easy::new();
     easy.fail_on_error(true)?; // -f
     easy.follow_location(true)?; // -L
+    configure(&mut easy, opts, headers)?;
     easy.url(url.as_ref())?;
-    let mut transfer = easy.transfer();
-    transfer.write_function(move |data| {
-        match file.write_all(data) {
-            Ok(()) => Ok(data.len()),
-            Err(_) => Ok(0), // signals to cURL that the writing failed
-        }
-    })?;
-    transfer.perform()?;
-    Ok(())
+    let mut received = Validators::default();
+    {
+        let mut transfer = easy.transfer();
+        transfer.header_function(|header| {
+            let header = String::from_utf8_lossy(header);
+            if header.starts_with("HTTP/") {
+                // A new response after following a redirect.
+                received = Validators::default();
+            } else if let Some((name, value)) = header.split_once(':') {
+                let value = Some(value.trim().to_string());
+                if name.eq_ignore_ascii_case("etag") {
+                    received.etag = value;
+                } else if name.eq_ignore_ascii_case("last-modified") {
+                    received.last_modified = value;
+                }
+            }
+            true
+        })?;
+        transfer.write_function(move |data| {
+            match file.write_all(data) {
+                Ok(()) => Ok(data.len()),
+                Err(_) => Ok(0), // signals to cURL that the writing failed
+            }
+        })?;
+        transfer.perform()?;
+    }
+    if easy.response_code()? == 304 {
+        Ok(None)
+    } else {
+        Ok(Some(received))
+    }
+}
+
+/// The response to a JSON API request.
+#[derive(Debug)]
+pub struct JsonResponse {
+    /// The response body.
+    pub body: Vec<u8>,
+    /// The URL of the next page of results, from the `Link` header.
+    pub next: Option<String>,
+}
+
+/// Fetch the response to a JSON API request.
+pub fn fetch_json(url: &str, opts: &RequestOptions) -> result::Result<JsonResponse, curl::Error> {
+    let mut headers = curl::easy::List::new();
+    headers.append("Accept: application/json")?;
+    let mut easy = curl::easy::Easy::new();
+    easy.fail_on_error(true)?; // -f
+    easy.follow_location(true)?; // -L
+    easy.useragent(&format!("{}/{}", build::CRATE_NAME, build::CRATE_RELEASE))?;
+    configure(&mut easy, opts, headers)?;
+    easy.url(url)?;
+    let mut body = Vec::new();
+    let mut next = None;
+    {
+        let mut transfer = easy.transfer();
+        transfer.header_function(|header| {
+            let header = String::from_utf8_lossy(header);
+            if header.starts_with("HTTP/") {
+                // A new response after following a redirect.
+                next = None;
+            } else if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("link") {
+                    next = next_link(value);
+                }
+            }
+            true
+        })?;
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+    Ok(JsonResponse { body, next })
+}
+
+/// Returns the URL with `rel="next"` in the value of a `Link` header, e.g.
+/// `<https://api.github.com/...&page=2>; rel="next", <...>; rel="last"`.
+fn next_link(value: &str) -> Option<String> {
+    value.split(',').find_map(|link| {
+        let mut parts = link.split(';').map(str::trim);
+        let url = parts.next()?.strip_prefix('<')?.strip_suffix('>')?;
+        parts
+            .any(|param| param == "rel=\"next\"")
+            .then(|| url.to_string())
+    })
+}
+
+/// Returns the hex encoded SHA-256 checksum of a file.
+pub fn sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
 }
@@ -0,0 +1,96 @@
+//! Read credentials from a netrc file.
+
+/// A `machine` or `default` entry in a netrc file.
+#[derive(Default)]
+struct Entry<'a> {
+    /// The host name, or `None` for the `default` entry.
+    machine: Option<&'a str>,
+    login: Option<&'a str>,
+    password: Option<&'a str>,
+}
+
+/// Returns the login and password for a host from the contents of a netrc
+/// file.
+///
+/// The `default` entry is used if there is no entry for the host.
+pub fn find(contents: &str, host: &str) -> Option<(String, String)> {
+    let mut entries: Vec<Entry<'_>> = Vec::new();
+    let mut in_macdef = false;
+    for line in contents.lines() {
+        // A macro definition continues until the next empty line.
+        if in_macdef {
+            in_macdef = !line.trim().is_empty();
+            continue;
+        }
+        let mut tokens = line.split_whitespace();
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" => entries.push(Entry {
+                    machine: Some(tokens.next().unwrap_or_default()),
+                    ..Default::default()
+                }),
+                "default" => entries.push(Entry::default()),
+                "login" | "password" | "account" => {
+                    let value = tokens.next();
+                    if let Some(entry) = entries.last_mut() {
+                        match token {
+                            "login" => entry.login = value,
+                            "password" => entry.password = value,
+                            _ => {}
+                        }
+                    }
+                }
+                "macdef" => {
+                    in_macdef = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+    let entry = entries
+        .iter()
+        .find(|e| e.machine == Some(host))
+        .or_else(|| entries.iter().find(|e| e.machine.is_none()))?;
+    Some((
+        entry.login.unwrap_or_default().to_string(),
+        entry.password?.to_string(),
+    ))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Unit tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn netrc_find() {
+        let contents = "
+machine github.com login octocat password ghp_token
+
+macdef init
+machine ignored.com login nope password nope
+
+machine gitlab.corp.com
+    login deploy
+    password glpat-token
+default login anonymous password guest
+";
+        assert_eq!(
+            find(contents, "github.com"),
+            Some(("octocat".into(), "ghp_token".into()))
+        );
+        assert_eq!(
+            find(contents, "gitlab.corp.com"),
+            Some(("deploy".into(), "glpat-token".into()))
+        );
+        assert_eq!(
+            find(contents, "ignored.com"),
+            Some(("anonymous".into(), "guest".into()))
+        );
+        assert_eq!(find("machine github.com login octocat", "github.com"), None);
+    }
+}
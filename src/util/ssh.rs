@@ -0,0 +1,146 @@
+//! Read identity files from an OpenSSH client configuration file.
+
+use std::path::{Path, PathBuf};
+
+use globset::Glob;
+
+/// The identity files that OpenSSH tries when none are configured.
+pub const DEFAULT_IDENTITY_FILES: &[&str] = &[
+    "id_rsa",
+    "id_ecdsa",
+    "id_ecdsa_sk",
+    "id_ed25519",
+    "id_ed25519_sk",
+    "id_dsa",
+];
+
+/// Whether a `Host` line's patterns match the host.
+///
+/// A host matches if it matches any pattern and none of the negated patterns.
+fn host_matches(patterns: &str, host: &str) -> bool {
+    let is_match = |pattern| {
+        Glob::new(pattern)
+            .map(|glob| glob.compile_matcher().is_match(host))
+            .unwrap_or(false)
+    };
+    let mut matched = false;
+    for pattern in patterns.split_whitespace() {
+        match pattern.strip_prefix('!') {
+            Some(pattern) if is_match(pattern) => return false,
+            Some(_) => {}
+            None => matched |= is_match(pattern),
+        }
+    }
+    matched
+}
+
+/// Expand the tilde and the `%d`, `%h`, and `%%` tokens in an `IdentityFile`.
+fn expand(value: &str, host: &str, home: &Path) -> PathBuf {
+    let mut expanded = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('%', Some('d')) => expanded.push_str(&home.to_string_lossy()),
+            ('%', Some('h')) => expanded.push_str(host),
+            ('%', Some('%')) => expanded.push('%'),
+            _ => {
+                expanded.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    match Path::new(&expanded).strip_prefix("~") {
+        Ok(path) => home.join(path),
+        Err(_) => PathBuf::from(expanded),
+    }
+}
+
+/// Returns the `IdentityFile` paths for a host from the contents of an SSH
+/// config file, in the order they are given.
+///
+/// `Match` blocks are not supported and are skipped.
+pub fn identity_files(contents: &str, host: &str, home: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    // Options before the first `Host` line apply to every host.
+    let mut active = true;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // The keyword is separated from the arguments by whitespace and/or a
+        // single equals sign.
+        let (keyword, args) = line
+            .split_once(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or((line, ""));
+        let args = args.trim_start();
+        let args = args.strip_prefix('=').unwrap_or(args).trim();
+        match keyword.to_lowercase().as_str() {
+            "host" => active = host_matches(args, host),
+            "match" => active = false,
+            "identityfile" if active => {
+                let value = args.trim_matches('"');
+                if !value.eq_ignore_ascii_case("none") {
+                    files.push(expand(value, host, home));
+                }
+            }
+            _ => {}
+        }
+    }
+    files
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Unit tests
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_files_for_host() {
+        let contents = r#"
+# Global options.
+IdentityFile ~/.ssh/id_global
+
+Host github.com gitlab.*
+    User git
+    IdentityFile ~/.ssh/id_%h
+
+Host *.corp.com !public.corp.com
+    IdentityFile="%d/keys/deploy key"
+
+Match host github.com
+    IdentityFile ~/.ssh/id_match
+
+host=*
+    identityfile /etc/ssh/id_shared
+"#;
+        let home = Path::new("/home/user");
+        assert_eq!(
+            identity_files(contents, "github.com", home),
+            [
+                PathBuf::from("/home/user/.ssh/id_global"),
+                PathBuf::from("/home/user/.ssh/id_github.com"),
+                PathBuf::from("/etc/ssh/id_shared"),
+            ]
+        );
+        assert_eq!(
+            identity_files(contents, "git.corp.com", home),
+            [
+                PathBuf::from("/home/user/.ssh/id_global"),
+                PathBuf::from("/home/user/keys/deploy key"),
+                PathBuf::from("/etc/ssh/id_shared"),
+            ]
+        );
+        assert_eq!(
+            identity_files(contents, "public.corp.com", home),
+            [
+                PathBuf::from("/home/user/.ssh/id_global"),
+                PathBuf::from("/etc/ssh/id_shared"),
+            ]
+        );
+    }
+}
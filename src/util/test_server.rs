@@ -0,0 +1,106 @@
+//! A local HTTP server for tests.
+
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use url::Url;
+
+/// The head of a request received by a [`Server`].
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+}
+
+/// A local HTTP server that handles each connection in turn on a background
+/// thread, and records the path of each request.
+pub struct Server {
+    url: Url,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl Request {
+    /// Read the head of a request, leaving any body in the stream.
+    fn read(stream: &mut TcpStream) -> Self {
+        let mut head = Vec::new();
+        let mut byte = [0];
+        while !head.ends_with(b"\r\n\r\n") {
+            if stream.read(&mut byte).unwrap() == 0 {
+                break;
+            }
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let mut lines = head.lines();
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let method = request_line.next().unwrap_or_default().to_string();
+        let path = request_line.next().unwrap_or_default().to_string();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+            .collect();
+        Self {
+            method,
+            path,
+            headers,
+        }
+    }
+
+    /// Returns the value of a header, if it was sent.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Server {
+    /// Start a server that handles each request with the given function,
+    /// which writes the response to the stream.
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&Request, &mut TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = Request::read(&mut stream);
+                recorded.lock().unwrap().push(request.path.clone());
+                handler(&request, &mut stream);
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// Returns the URL of a path on the server.
+    pub fn url(&self, path: &str) -> Url {
+        self.url.join(path).unwrap()
+    }
+
+    /// Returns the paths requested since this was last called.
+    pub fn take_requests(&self) -> Vec<String> {
+        std::mem::take(&mut *self.requests.lock().unwrap())
+    }
+}
+
+/// Write a response with the given status, extra headers, and body.
+pub fn respond(stream: &mut TcpStream, status: &str, headers: &[(&str, &str)], body: &[u8]) {
+    let mut response = format!("HTTP/1.1 {status}\r\n");
+    for (name, value) in headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    ));
+    let mut response = response.into_bytes();
+    response.extend_from_slice(body);
+    stream.write_all(&response).unwrap();
+}
@@ -5,9 +5,12 @@ use std::env;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time;
 
 use once_cell::sync::Lazy;
 use pretty_assertions::assert_eq;
+use sha2::{Digest, Sha256};
 
 use crate::helpers::{TestCommand, TestDirs};
 
@@ -15,6 +18,10 @@ use crate::helpers::{TestCommand, TestDirs};
 // Utilities
 ////////////////////////////////////////////////////////////////////////////////
 
+/// The path of the downloaded test plugin, relative to the data directory.
+const DOWNLOADED_TEST_PLUGIN: &str =
+    "downloads/raw.githubusercontent.com/rossmacarthur/sheldon-test/master/test.plugin.zsh";
+
 struct TestCase {
     dirs: TestDirs,
     data: HashMap<String, String>,
@@ -41,11 +48,15 @@ impl TestCase {
             p
         };
 
+        // Lock files store these directories relative to the home directory.
+        let tilde = |path: &Path| Path::new("~").join(path.strip_prefix(dirs.home.path()).unwrap());
         let subs = upon::value! {
             version: env!("CARGO_PKG_VERSION"),
             home: dirs.home.path(),
             config: &dirs.config,
             data: &dirs.data,
+            tilde_config: tilde(&dirs.config),
+            tilde_data: tilde(&dirs.data),
         };
 
         let mut data = HashMap::new();
@@ -93,6 +104,17 @@ impl TestCase {
         Ok(())
     }
 
+    /// Assert the contents of a lock file, filling in the checksum of the given
+    /// downloaded file since it depends on the remote contents.
+    fn assert_lock_file_path(&self, path: &Path, download: &Path) -> io::Result<()> {
+        let sha256 = format!("{:x}", Sha256::digest(fs::read(download)?));
+        assert_eq!(
+            fs::read_to_string(path)?,
+            self.get("plugins.lock").replace("{{ sha256 }}", &sha256)
+        );
+        Ok(())
+    }
+
     fn run(&self) -> io::Result<()> {
         self.write_config_file("plugins.toml")?;
         self.command("lock").run()?;
@@ -234,7 +256,9 @@ fn lock_and_source_github_branch() -> io::Result<()> {
 #[test]
 fn lock_and_source_github_submodule() -> io::Result<()> {
     let case = TestCase::load("github_submodule")?;
-    case.run()?;
+    case.write_config_file("plugins.toml")?;
+    case.command("lock").run()?;
+    case.command("source").run()?;
 
     // Check that sheldon-test@recursive-recursive was in fact cloned.
     let dir = case
@@ -252,6 +276,12 @@ fn lock_and_source_github_submodule() -> io::Result<()> {
     );
     // working tree clean
     assert!(repo.status().unwrap().is_empty());
+    // the lock file records the checked out commit
+    let commit = repo.revparse_commit("HEAD").unwrap().id().to_string();
+    assert_eq!(
+        fs::read_to_string(case.dirs.data.join("plugins.lock"))?,
+        case.get("plugins.lock").replace("{{ commit }}", &commit)
+    );
 
     // Check that sheldon-test@recursive submodule self was in fact cloned.
     let dir = dir.join("self");
@@ -292,6 +322,55 @@ fn lock_and_source_github_tag() -> io::Result<()> {
     Ok(())
 }
 
+#[test]
+fn lock_and_source_github_version() -> io::Result<()> {
+    let case = TestCase::load("github_version")?;
+    case.run()?;
+    check_sheldon_test(&case.dirs.data).unwrap();
+    Ok(())
+}
+
+#[test]
+fn lock_and_source_github_multiple_refs() -> io::Result<()> {
+    let case = TestCase::load("github_multiple_refs")?;
+    case.run()?;
+    check_sheldon_test(&case.dirs.data).unwrap();
+    let dir = case
+        .dirs
+        .data
+        .join("repos/github.com/rossmacarthur/sheldon-test@v0.1.0");
+    assert!(dir.join("test.plugin.zsh").is_file());
+    Ok(())
+}
+
+#[test]
+fn lock_github_locked_commit() -> io::Result<()> {
+    let case = TestCase::load("github_https")?;
+    case.run()?;
+
+    // Simulate a fresh machine with a lock file that pins an older commit.
+    let data = &case.dirs.data;
+    let lock_file = data.join("plugins.lock");
+    let contents = fs::read_to_string(&lock_file)?.replace(
+        "be8fde277e76f35efbe46848fb352cee68549962",
+        "ad149784a1538291f2477fb774eeeed4f4d29e45",
+    );
+    fs::write(&lock_file, contents)?;
+    fs::remove_dir_all(data.join("repos"))?;
+    case.command("lock").run()?;
+
+    let dir = data.join("repos/github.com/rossmacarthur/sheldon-test");
+    let repo = git2::Repository::open(dir).unwrap();
+    assert_eq!(
+        repo.revparse_commit("HEAD").unwrap().id().to_string(),
+        "ad149784a1538291f2477fb774eeeed4f4d29e45"
+    );
+    assert!(fs::read_to_string(&lock_file)?
+        .contains("commit = \"ad149784a1538291f2477fb774eeeed4f4d29e45\""));
+
+    Ok(())
+}
+
 #[test]
 fn lock_and_source_github_bad_url() -> io::Result<()> {
     let case = TestCase::load("github_bad_url")?;
@@ -325,6 +404,213 @@ fn lock_and_source_github_bad_reinstall() -> io::Result<()> {
     Ok(())
 }
 
+#[test]
+fn lock_frozen() -> io::Result<()> {
+    let case = TestCase::load("github_tag")?;
+    case.run()?;
+
+    // The lock file is up to date.
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(0)
+        .arg("lock")
+        .arg("--frozen")
+        .run()?;
+
+    // The config changed so the lock file is out of date, but nothing is
+    // changed.
+    let case = TestCase::load_with_dirs("github_branch", case.dirs)?;
+    case.write_config_file("plugins.toml")?;
+    let lock_file = case.dirs.data.join("plugins.lock");
+    let contents = fs::read_to_string(&lock_file)?;
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(2)
+        .arg("lock")
+        .arg("--frozen")
+        .run()?;
+    assert_eq!(fs::read_to_string(&lock_file)?, contents);
+    check_sheldon_test(&case.dirs.data).unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn lock_update_plugin() -> io::Result<()> {
+    let case = TestCase::load("directories_default")?;
+    case.write_config_file("plugins.toml")?;
+    case.command("lock").run()?;
+
+    // Pin the Git plugin to an older commit.
+    let data = &case.dirs.data;
+    let lock_file = data.join("plugins.lock");
+    let contents = fs::read_to_string(&lock_file)?.replace(
+        "be8fde277e76f35efbe46848fb352cee68549962",
+        "ad149784a1538291f2477fb774eeeed4f4d29e45",
+    );
+    fs::write(&lock_file, contents)?;
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(0)
+        .arg("lock")
+        .run()?;
+    let repo =
+        git2::Repository::open(data.join("repos/github.com/rossmacarthur/sheldon-test")).unwrap();
+    assert_eq!(
+        repo.revparse_commit("HEAD").unwrap().id().to_string(),
+        "ad149784a1538291f2477fb774eeeed4f4d29e45"
+    );
+
+    // Only the named plugin is updated.
+    let download = data.join(DOWNLOADED_TEST_PLUGIN);
+    let modified = fs::metadata(&download)?.modified()?;
+    thread::sleep(time::Duration::from_secs(1));
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(0)
+        .arg("lock")
+        .arg("--update")
+        .arg("testb")
+        .run()?;
+    assert_eq!(
+        repo.revparse_commit("HEAD").unwrap().id().to_string(),
+        "be8fde277e76f35efbe46848fb352cee68549962"
+    );
+    assert_eq!(fs::metadata(&download)?.modified()?, modified);
+
+    // Unknown plugins are an error.
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(2)
+        .arg("lock")
+        .arg("--update")
+        .arg("testc")
+        .run()?;
+
+    Ok(())
+}
+
+#[test]
+fn lock_update_log() -> io::Result<()> {
+    let case = TestCase::load("github_https")?;
+    case.run()?;
+
+    // Check out an older commit and then update with a log.
+    let dir = case
+        .dirs
+        .data
+        .join("repos/github.com/rossmacarthur/sheldon-test");
+    let repo = git2::Repository::open(dir).unwrap();
+    let older = repo
+        .revparse_single("ad149784a1538291f2477fb774eeeed4f4d29e45")
+        .unwrap();
+    repo.reset(&older, git2::ResetType::Hard, None).unwrap();
+
+    let mut expected = String::from("test (ad14978 to be8fde2)\n");
+    let mut revwalk = repo.revwalk().unwrap();
+    revwalk.push_ref("refs/remotes/origin/HEAD").unwrap();
+    revwalk.hide(older.id()).unwrap();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid.unwrap()).unwrap();
+        let id = commit.id().to_string();
+        expected.push_str(&format!("  {} {}\n", &id[..7], commit.summary().unwrap()));
+    }
+
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(0)
+        .expect_stdout(expected)
+        .arg("lock")
+        .arg("--update")
+        .arg("--log")
+        .run()?;
+
+    Ok(())
+}
+
+#[test]
+fn rollback_github() -> io::Result<()> {
+    let case = TestCase::load("github_https")?;
+    case.run()?;
+
+    // Pin an older commit, then update.
+    let data = &case.dirs.data;
+    let lock_file = data.join("plugins.lock");
+    let latest = fs::read_to_string(&lock_file)?;
+    let older = latest.replace(
+        "be8fde277e76f35efbe46848fb352cee68549962",
+        "ad149784a1538291f2477fb774eeeed4f4d29e45",
+    );
+    fs::write(&lock_file, &older)?;
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(0)
+        .arg("lock")
+        .run()?;
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(0)
+        .arg("lock")
+        .arg("--update")
+        .run()?;
+    assert_eq!(fs::read_to_string(&lock_file)?, latest);
+
+    // Roll back to the older commit.
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(0)
+        .arg("rollback")
+        .run()?;
+    assert_eq!(fs::read_to_string(&lock_file)?, older);
+    let repo =
+        git2::Repository::open(data.join("repos/github.com/rossmacarthur/sheldon-test")).unwrap();
+    assert_eq!(
+        repo.revparse_commit("HEAD").unwrap().id().to_string(),
+        "ad149784a1538291f2477fb774eeeed4f4d29e45"
+    );
+
+    // There is nothing further back than the first lock file.
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(2)
+        .arg("rollback")
+        .arg("--to")
+        .arg("3")
+        .run()?;
+
+    Ok(())
+}
+
+#[test]
+fn outdated_github() -> io::Result<()> {
+    let case = TestCase::load("github_https")?;
+    case.run()?;
+
+    // Everything is up to date.
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(0)
+        .expect_stdout(String::new())
+        .arg("outdated")
+        .run()?;
+
+    // Check out an older commit so that the plugin is behind upstream.
+    let dir = case
+        .dirs
+        .data
+        .join("repos/github.com/rossmacarthur/sheldon-test");
+    let repo = git2::Repository::open(dir).unwrap();
+    let older = repo
+        .revparse_single("ad149784a1538291f2477fb774eeeed4f4d29e45")
+        .unwrap();
+    repo.reset(&older, git2::ResetType::Hard, None).unwrap();
+    let latest = repo.revparse_commit("origin/HEAD").unwrap().id();
+    let (_, behind) = repo.graph_ahead_behind(older.id(), latest).unwrap();
+
+    TestCommand::new(&case.dirs)
+        .expect_exit_code(0)
+        .expect_stdout(format!(
+            "NAME  CURRENT  AVAILABLE  BEHIND  NEWER TAGS\n\
+             test  ad14978  be8fde2    {behind}\n"
+        ))
+        .arg("outdated")
+        .run()?;
+
+    // Nothing was checked out.
+    assert_eq!(repo.revparse_commit("HEAD").unwrap().id(), older.id());
+
+    Ok(())
+}
+
 #[test]
 fn lock_and_source_inline() -> io::Result<()> {
     TestCase::load("inline")?.run()
@@ -399,7 +685,10 @@ fn directories_old() -> io::Result<()> {
         .env_remove("SHELDON_CONFIG_DIR")
         .env_remove("SHELDON_DATA_DIR")
         .run()?;
-    case.assert_contents_path("plugins.lock", &config_dir.join("plugins.lock"))?;
+    case.assert_lock_file_path(
+        &config_dir.join("plugins.lock"),
+        &config_dir.join(DOWNLOADED_TEST_PLUGIN),
+    )?;
     case.command("source")
         .env_remove("SHELDON_CONFIG_DIR")
         .env_remove("SHELDON_DATA_DIR")
@@ -413,7 +702,10 @@ fn directories_default() -> io::Result<()> {
     let case = TestCase::load_with_dirs("directories_default", dirs)?;
     case.write_config_file("plugins.toml")?;
     case.command("lock").run()?;
-    case.assert_contents("plugins.lock")?;
+    case.assert_lock_file_path(
+        &case.dirs.data.join("plugins.lock"),
+        &case.dirs.data.join(DOWNLOADED_TEST_PLUGIN),
+    )?;
     case.command("source").run()?;
     case.dirs.assert_conforms();
     Ok(())
@@ -435,7 +727,10 @@ fn directories_xdg_from_env() -> io::Result<()> {
         .env_remove("SHELDON_DATA_DIR")
         .envs(envs)
         .run()?;
-    case.assert_contents("plugins.lock")?;
+    case.assert_lock_file_path(
+        &case.dirs.data.join("plugins.lock"),
+        &case.dirs.data.join(DOWNLOADED_TEST_PLUGIN),
+    )?;
     case.command("source")
         .env_remove("SHELDON_CONFIG_DIR")
         .env_remove("SHELDON_DATA_DIR")